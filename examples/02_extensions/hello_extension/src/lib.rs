@@ -18,6 +18,7 @@ fn entry_point(
 }
 
 // Workaround: lua_module macro reimplemented for luau
+#[unsafe(no_mangle)]
 pub unsafe extern "C-unwind" fn luaopen_native(
     state: *mut lua_State
 ) -> c_int {
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use ansi_term::Color::{Green, Red, Yellow};
+use ansi_term::Style;
+use anyhow::{bail, Context, Result};
+use crate::lock::{lock_file_path, LockFile, LockedNative, LOCK_FILE_NAME};
+use crate::ludorc::{Blessing, UserRc, UserRcStore};
+use crate::native::{collect_transitive_natives, content_hash, BlessInfo, TransitiveNative};
+use crate::run::ScriptContext;
+
+enum BlessAnswer {
+    Yes,
+    No,
+    All,
+    Quit
+}
+
+fn prompt(
+    question: &str
+) -> Result<String> {
+    print!("{question} ");
+    stdout().flush().context("Failed to flush standard output")?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer).context("Failed to read answer from standard input")?;
+    Ok(answer.trim().to_lowercase())
+}
+
+fn ask_to_bless() -> Result<BlessAnswer> {
+    loop {
+        let answer = prompt("   Bless this library? [y]es, [n]o, [a]ll remaining, [q]uit:")?;
+        match answer.as_str() {
+            "y" | "yes" => return Ok(BlessAnswer::Yes),
+            "n" | "no" => return Ok(BlessAnswer::No),
+            "a" | "all" => return Ok(BlessAnswer::All),
+            // An empty answer also covers standard input being closed.
+            "" | "q" | "quit" => return Ok(BlessAnswer::Quit),
+            _ => println!("   Please answer y, n, a or q.")
+        }
+    }
+}
+
+pub fn print_hash_from_fs(
+    binary: &Path
+) -> Result<()> {
+    let bytes = fs::read(binary).with_context(|| format!("Could not read native binary at {}", binary.display()))?;
+    println!("{}", content_hash(&bytes));
+    Ok(())
+}
+
+pub fn print_bless_info(
+    bless: &BlessInfo
+) {
+    println!("-> {}", Yellow.bold().paint(&bless.title));
+    println!("   hash: {}", &bless.hash);
+    println!("   {}", Style::new().dimmed().paint(bless.path.display().to_string()));
+}
+
+fn save_blessings(
+    accepted: &[&BlessInfo],
+    reason: Option<String>
+) -> Result<()> {
+    let count = accepted.len();
+    let store = UserRcStore::at_home()?;
+    store.modify(|user_rc| {
+        for bless in accepted {
+            user_rc.bless(bless, reason.clone());
+        }
+        Ok(())
+    }).context("Failed to save user .ludorc")?;
+    println!("{}", Green.paint(format!("Blessed {count} native {}.", if count == 1 { "library" } else { "libraries" })));
+    println!("{}", Style::new().dimmed().paint(format!("Saved to {}", store.path().display())));
+    Ok(())
+}
+
+/// Blesses every native library which matches the project's lock file, without prompting.
+/// Fails without blessing anything if some library doesn't match.
+fn bless_locked(
+    not_blessed: &[&TransitiveNative],
+    script_location: &Path,
+    reason: Option<String>
+) -> Result<()> {
+    let lock_path = lock_file_path(script_location)?;
+    let lock_directory = lock_path.parent().context("Lock file must exist inside of a directory")?;
+    let lock = LockFile::load(&lock_path)?
+        .with_context(|| format!("No {LOCK_FILE_NAME} found at {}", lock_path.display()))?;
+    let mut unlocked = vec![];
+    for transitive_native in not_blessed {
+        if !lock.contains(&LockedNative::new(transitive_native, lock_directory)?) {
+            unlocked.push(transitive_native);
+        }
+    }
+    if !unlocked.is_empty() {
+        for transitive_native in unlocked {
+            print_bless_info(&transitive_native.bless);
+            println!();
+        }
+        bail!("These native libraries don't match {}; bless them individually with `ludo bless` after verifying them", lock_path.display());
+    }
+    for transitive_native in not_blessed {
+        print_bless_info(&transitive_native.bless);
+        println!();
+    }
+    let reason = reason.or_else(|| Some(format!("Matched {}", lock_path.display())));
+    let accepted: Vec<_> = not_blessed.iter().map(|x| &x.bless).collect();
+    save_blessings(&accepted, reason)
+}
+
+/// Walks the user through blessing each native library, saving the ones they accept.
+/// Returns the hashes which were blessed.
+pub fn bless_interactively(
+    not_blessed: &[&TransitiveNative],
+    all: bool,
+    reason: Option<String>
+) -> Result<HashSet<String>> {
+    println!();
+    println!("Blessing a native library allows it to run with {}.", Red.bold().paint("full access to your system"));
+    println!("Only bless libraries whose hashes match the public hash published by their author.");
+    println!();
+
+    let mut accepted = vec![];
+    if all {
+        for transitive_native in not_blessed {
+            print_bless_info(&transitive_native.bless);
+            println!("   entry point: {}", transitive_native.native.entry_point.to_string_lossy());
+            println!();
+        }
+        let answer = prompt(&format!("Bless all {} of these libraries? [y]es, [n]o:", not_blessed.len()))?;
+        if matches!(answer.as_str(), "y" | "yes") {
+            accepted.extend(not_blessed.iter().map(|x| &x.bless));
+        }
+    } else {
+        let mut bless_remaining = false;
+        for transitive_native in not_blessed {
+            print_bless_info(&transitive_native.bless);
+            println!("   entry point: {}", transitive_native.native.entry_point.to_string_lossy());
+            if !bless_remaining {
+                match ask_to_bless()? {
+                    BlessAnswer::Yes => (),
+                    BlessAnswer::No => { println!(); continue },
+                    BlessAnswer::All => bless_remaining = true,
+                    BlessAnswer::Quit => break
+                }
+            }
+            println!();
+            accepted.push(&transitive_native.bless);
+        }
+    }
+
+    println!();
+    if accepted.is_empty() {
+        println!("No libraries were blessed.");
+        return Ok(HashSet::new());
+    }
+    save_blessings(&accepted, reason)?;
+    Ok(accepted.into_iter().map(|bless| bless.hash.clone()).collect())
+}
+
+pub fn bless_from_fs(
+    user_rc: Rc<UserRc>,
+    script_location: PathBuf,
+    all: bool,
+    locked: bool,
+    reason: Option<String>
+) -> Result<()> {
+    let context = ScriptContext::new_from_fs(user_rc, script_location.clone()).context("Failed to construct script context")?;
+    let transitive_natives = collect_transitive_natives(&context)?;
+    let mut seen_hashes = HashSet::new();
+    let not_blessed: Vec<_> = transitive_natives.iter()
+        .filter(|x| !x.is_blessed())
+        .filter(|x| seen_hashes.insert(x.bless.hash.clone()))
+        .collect();
+    if not_blessed.is_empty() {
+        println!("All native libraries used by this script are already blessed.");
+        return Ok(());
+    }
+    if locked {
+        return bless_locked(&not_blessed, &script_location, reason);
+    }
+    bless_interactively(&not_blessed, all, reason)?;
+    Ok(())
+}
+
+fn print_blessing(
+    hash: &str,
+    blessing: &Blessing,
+    legacy: bool
+) {
+    let title = blessing.title.as_deref().unwrap_or("(unknown library)");
+    if legacy {
+        println!("-> {} {}", Yellow.bold().paint(title), Style::new().dimmed().paint("(legacy)"));
+        println!("   legacy hash: {hash}");
+    } else {
+        println!("-> {}", Yellow.bold().paint(title));
+        println!("   hash: {hash}");
+    }
+    if let Some(path) = &blessing.path {
+        let missing = if path.is_file() { "" } else { " (missing)" };
+        println!("   {}{}", Style::new().dimmed().paint(path.display().to_string()), Red.paint(missing));
+    }
+    if let Some(blessed_at) = &blessing.blessed_at {
+        println!("   blessed at: {blessed_at}");
+    }
+    if let Some(reason) = &blessing.reason {
+        println!("   reason: {reason}");
+    }
+}
+
+fn contains_text(
+    haystack: Option<String>,
+    needle: &Option<String>
+) -> bool {
+    match needle {
+        None => true,
+        Some(needle) => haystack.is_some_and(|haystack| haystack.to_lowercase().contains(&needle.to_lowercase()))
+    }
+}
+
+/// Lists blessings, legacy ones included, as those still let their binaries run.
+pub fn list_blessings(
+    user_rc: &UserRc,
+    name: Option<String>,
+    path: Option<String>
+) -> Result<()> {
+    let matching: Vec<_> = user_rc.blessed.iter().map(|(hash, blessing)| (hash, blessing, false))
+        .chain(user_rc.legacy_blessed.iter().map(|(hash, blessing)| (hash, blessing, true)))
+        .filter(|(_, blessing, _)| contains_text(blessing.title.clone(), &name))
+        .filter(|(_, blessing, _)| contains_text(blessing.path.as_ref().map(|x| x.display().to_string()), &path))
+        .collect();
+    if matching.is_empty() {
+        println!("No matching blessings found.");
+        return Ok(());
+    }
+    for (hash, blessing, legacy) in matching {
+        print_blessing(hash, blessing, legacy);
+        println!();
+    }
+    Ok(())
+}
+
+/// Revokes blessings by hash, or all at once. `missing` revokes those whose binary no longer
+/// exists, and `legacy` those which can't be checked, as they either record no binary or were
+/// made with a legacy hash which could not be upgraded.
+pub fn unbless(
+    hash: Option<String>,
+    all: bool,
+    missing: bool,
+    legacy: bool
+) -> Result<()> {
+    let store = UserRcStore::at_home()?;
+    let revoked = store.modify(|user_rc| {
+        Ok(user_rc.unbless(|blessed_hash, blessing, is_legacy| {
+            all
+                || hash.as_deref() == Some(blessed_hash)
+                || missing && blessing.path.as_ref().is_some_and(|path| !path.is_file())
+                || legacy && (is_legacy || blessing.path.is_none())
+        }))
+    }).context("Failed to save user .ludorc")?;
+
+    if revoked.is_empty() {
+        if let Some(hash) = hash {
+            bail!("No blessing found with hash {hash}");
+        }
+        println!("No blessings were revoked.");
+        return Ok(());
+    }
+    for (hash, blessing, legacy) in &revoked {
+        print_blessing(hash, blessing, *legacy);
+        println!();
+    }
+    let count = revoked.len();
+    println!("{}", Green.paint(format!("Revoked {count} {}.", if count == 1 { "blessing" } else { "blessings" })));
+    Ok(())
+}
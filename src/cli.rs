@@ -1,17 +1,54 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use std::time::Duration;
+use crate::error::ErrorFormat;
+use crate::limits::{parse_memory_size, Limits};
+use crate::trust::TrustPolicy;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Args {
     #[arg(value_name = "PATH")]
     pub file_to_run: Option<PathBuf>,
 
+    /// Arguments passed to the script as varargs, given after `--`
+    #[arg(last = true, value_name = "ARGS")]
+    pub script_args: Vec<String>,
+
+    /// How to treat native libraries that haven't been blessed
+    #[arg(long, global = true, value_enum, value_name = "POLICY", env = "LUDO_TRUST_POLICY", default_value_t = TrustPolicy::Deny)]
+    pub trust_policy: TrustPolicy,
+
+    /// A file of pre-approved native hashes, one per line, used by the allow-listed trust policy
+    #[arg(long, global = true, value_name = "FILE", env = "LUDO_TRUSTED_HASHES_FILE")]
+    pub trusted_hashes: Option<PathBuf>,
+
+    /// The most memory a script's Luau heap may use, such as 256MiB
+    #[arg(long, global = true, value_name = "SIZE", env = "LUDO_MAX_MEMORY", value_parser = parse_memory_size)]
+    pub max_memory: Option<usize>,
+
+    /// How long a script may run for, such as 30s or 5m
+    #[arg(long, global = true, value_name = "DURATION", env = "LUDO_TIMEOUT", value_parser = humantime::parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// How many function calls and loop iterations a script may make
+    #[arg(long, global = true, value_name = "COUNT", env = "LUDO_MAX_INSTRUCTIONS")]
+    pub max_instructions: Option<u64>,
+
+    /// How to print an error which stops Ludo
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", env = "LUDO_ERROR_FORMAT", default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+
     #[clap(subcommand)]
     pub command: Option<Commands>
 }
 
 impl Args {
+    pub fn limits(&self) -> Limits {
+        Limits { memory: self.max_memory, timeout: self.timeout, instructions: self.max_instructions }
+    }
+
     pub fn no_args_passed(&self) -> bool {
         self.file_to_run.is_none() && self.command.is_none()
     }
@@ -19,5 +56,166 @@ impl Args {
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    Bless
-}
\ No newline at end of file
+    /// Review and bless the native libraries a script would run
+    Bless {
+        #[arg(value_name = "PATH")]
+        file_to_bless: PathBuf,
+
+        /// Bless every unblessed native library without asking one at a time
+        #[arg(long)]
+        all: bool,
+
+        /// Bless every native library matching the project's ludo.lock, without prompting
+        #[arg(long, conflicts_with = "all")]
+        locked: bool,
+
+        /// Why these libraries are being blessed, kept alongside each blessing
+        #[arg(long)]
+        reason: Option<String>
+    },
+    /// Print the .luaurc and .ludorc settings which apply to a script, and where each came from
+    Config {
+        /// A script, or a directory of scripts; defaults to the current directory
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+
+        /// Print the settings as JSON
+        #[arg(long)]
+        json: bool
+    },
+    /// Print the hash of a native binary, exactly as its author should publish it
+    Hash {
+        #[arg(value_name = "PATH")]
+        binary: PathBuf
+    },
+    /// Manage publisher keys, which vouch for native binaries with signatures
+    Key {
+        #[clap(subcommand)]
+        command: KeyCommands
+    },
+    /// Record the native libraries a script runs in a ludo.lock file next to it
+    Lock {
+        #[arg(value_name = "PATH")]
+        file_to_lock: PathBuf,
+
+        /// Leave ludo.lock untouched, and fail if the native libraries no longer match it
+        #[arg(long)]
+        check: bool
+    },
+    /// List the native libraries you have blessed
+    Blessings {
+        /// Only list blessings whose library name contains this text
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Only list blessings whose path contains this text
+        #[arg(long)]
+        path: Option<String>
+    },
+    /// Rewrite .ludorc files written for older versions of Ludo in the current format
+    Migrate {
+        /// A .ludorc, or a directory in which to migrate every .ludorc; defaults to the current directory
+        #[arg(value_name = "PATH", conflicts_with = "user")]
+        path: Option<PathBuf>,
+
+        /// Migrate the user .ludorc in your home directory instead
+        #[arg(long)]
+        user: bool,
+
+        /// Print what each .ludorc would be rewritten as, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Rewrite files even though their comments and trailing commas will be lost
+        #[arg(long)]
+        force: bool
+    },
+    /// Open an interactive Luau prompt
+    Repl {
+        /// Apply the current directory's .ludorc and .luaurc, including its native library
+        #[arg(long)]
+        workspace: bool
+    },
+    /// Revoke blessings, so their native libraries must be blessed again before running
+    Unbless {
+        /// The hash of the blessing to revoke
+        #[arg(value_name = "HASH", required_unless_present_any = ["all", "missing", "legacy"])]
+        hash: Option<String>,
+
+        /// Revoke every blessing
+        #[arg(long, conflicts_with_all = ["hash", "missing", "legacy"])]
+        all: bool,
+
+        /// Revoke blessings for binaries that no longer exist on disk
+        #[arg(long, conflicts_with = "hash")]
+        missing: bool,
+
+        /// Revoke legacy blessings, which --missing can't check as they record no binary or
+        /// predate content hashes
+        #[arg(long, conflicts_with = "hash")]
+        legacy: bool
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyCommands {
+    /// Generate a new signing key for publishing native binaries
+    Generate {
+        /// Where to save the secret key file
+        #[arg(value_name = "FILE")]
+        key_file: PathBuf,
+
+        /// A name for the publisher, included with the key
+        #[arg(long)]
+        name: Option<String>
+    },
+    /// Sign a native binary, printing a signature to declare in its .ludorc
+    Sign {
+        /// The secret key file made by `ludo key generate`
+        #[arg(long, value_name = "FILE")]
+        key: PathBuf,
+
+        #[arg(value_name = "BINARY")]
+        binary: PathBuf
+    },
+    /// Check a native binary's signature
+    Verify {
+        #[arg(value_name = "BINARY")]
+        binary: PathBuf,
+
+        #[arg(long)]
+        signature: String,
+
+        /// The public key expected to have made the signature; defaults to any trusted key
+        #[arg(long)]
+        public_key: Option<String>
+    },
+    /// Trust a publisher, so native binaries they sign count as blessed
+    Trust {
+        public_key: String,
+
+        /// A name to remember the publisher by
+        #[arg(long)]
+        name: Option<String>
+    },
+    /// Stop trusting a publisher
+    Untrust {
+        public_key: String
+    },
+    /// List trusted publisher keys
+    List
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repl_accepts_trust_options() {
+        let args = Args::try_parse_from(["ludo", "repl", "--workspace", "--trust-policy", "allow-listed", "--trusted-hashes", "hashes.txt"])
+            .expect("Trust options should be accepted after the subcommand");
+        assert!(matches!(args.command, Some(Commands::Repl { workspace: true })));
+        assert_eq!(args.trust_policy, TrustPolicy::AllowListed);
+        assert_eq!(args.trusted_hashes.as_deref(), Some(std::path::Path::new("hashes.txt")));
+    }
+}
@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+use ansi_term::Style;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::limits::Limits;
+use crate::luaurc::{load_composite_luau_rc, load_luau_rc_layers};
+use crate::ludorc::{load_workspace_rc, load_workspace_rc_layers};
+
+/// Where settings given on the command line are said to come from.
+const COMMAND_LINE: &str = "command line";
+
+/// The settings made by one configuration file, or by the command line.
+struct Layer {
+    source: String,
+    settings: Vec<(String, Value)>
+}
+
+/// A setting's effective value, alongside the file it came from and those it took precedence over.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Setting {
+    pub key: String,
+    pub value: Value,
+    pub source: String,
+    pub overrides: Vec<String>
+}
+
+/// Attributes each of the `effective` settings to the last of `layers` which sets it, and lists the
+/// layers before it which set it too. `layers` are given lowest precedence first.
+fn attribute(
+    effective: Vec<(String, Value)>,
+    layers: &[Layer]
+) -> Vec<Setting> {
+    let mut settings: Vec<_> = effective.into_iter()
+        .map(|(key, value)| {
+            let mut sources: Vec<_> = layers.iter()
+                .filter(|layer| layer.settings.iter().any(|(setting, _)| *setting == key))
+                .map(|layer| layer.source.clone())
+                .collect();
+            let source = sources.pop().unwrap_or_default();
+            Setting { key, value, source, overrides: sources }
+        })
+        .collect();
+    settings.sort_by(|a, b| a.key.cmp(&b.key));
+    settings
+}
+
+fn luau_rc_settings(
+    directory: &Path
+) -> Result<Vec<Setting>> {
+    let effective = load_composite_luau_rc(directory)?;
+    let layers: Vec<_> = load_luau_rc_layers(directory)?
+        .into_iter()
+        .map(|(ancestor, rc)| Layer {
+            source: ancestor.join(".luaurc").display().to_string(),
+            settings: rc.settings()
+        })
+        .collect();
+    Ok(attribute(effective.settings(), &layers))
+}
+
+/// The settings of the .ludorc files which govern `directory`, exactly as scripts run from it see
+/// them, with limits given on the command line taking precedence.
+fn workspace_rc_settings(
+    directory: &Path,
+    limits: &Limits
+) -> Result<(Option<PathBuf>, Vec<Setting>)> {
+    let mut workspace_rc = load_workspace_rc(directory)?;
+    let mut layers: Vec<_> = load_workspace_rc_layers(directory)?
+        .iter()
+        .rev()
+        .map(|rc| Layer {
+            source: rc.root.as_deref().unwrap_or(directory).join(".ludorc").display().to_string(),
+            settings: rc.settings()
+        })
+        .collect();
+    layers.push(Layer { source: COMMAND_LINE.into(), settings: limits.settings() });
+    workspace_rc.limits = limits.or(workspace_rc.limits);
+    Ok((workspace_rc.root.clone(), attribute(workspace_rc.settings(), &layers)))
+}
+
+fn print_settings(
+    title: &str,
+    settings: &[Setting]
+) {
+    println!("{}", Style::new().bold().paint(title));
+    if settings.is_empty() {
+        println!("  {}", Style::new().dimmed().paint("(nothing set)"));
+    }
+    for setting in settings {
+        println!("  {} = {}", Style::new().bold().paint(&setting.key), setting.value);
+        let mut provenance = format!("from {}", setting.source);
+        if !setting.overrides.is_empty() {
+            provenance.push_str(&format!(", overriding {}", setting.overrides.join(", ")));
+        }
+        println!("      {}", Style::new().dimmed().paint(provenance));
+    }
+}
+
+/// Prints the .luaurc and .ludorc settings which apply to scripts in `directory`, and where each
+/// came from. Limits given on the command line are included, as they take precedence.
+pub fn print_config(
+    directory: &Path,
+    limits: &Limits,
+    json: bool
+) -> Result<()> {
+    let luau_rc = luau_rc_settings(directory).context("Failed to construct .luaurc")?;
+    let (workspace_root, workspace_rc) = workspace_rc_settings(directory, limits)
+        .context("Failed to construct workspace .ludorc")?;
+    if json {
+        let report = json!({ "workspace_root": workspace_root, "luaurc": luau_rc, "ludorc": workspace_rc });
+        println!("{}", serde_json::to_string_pretty(&report).context("Failed to encode configuration")?);
+        return Ok(());
+    }
+    let workspace_root = workspace_root.as_deref().unwrap_or(directory);
+    println!("{} {}", Style::new().dimmed().paint("Workspace root:"), workspace_root.display());
+    println!();
+    print_settings(".luaurc", &luau_rc);
+    println!();
+    print_settings(".ludorc", &workspace_rc);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+
+    fn layer(
+        source: &str,
+        settings: &[(&str, Value)]
+    ) -> Layer {
+        Layer {
+            source: source.into(),
+            settings: settings.iter().map(|(key, value)| (key.to_string(), value.clone())).collect()
+        }
+    }
+
+    #[test]
+    fn settings_record_what_they_override() {
+        let settings = attribute(
+            vec![
+                ("languageMode".into(), json!("nonstrict")),
+                ("aliases.lib".into(), json!("/p/lib")),
+                ("lint.*".into(), json!(false))
+            ],
+            &[
+                layer("/.luaurc", &[("languageMode", json!("strict")), ("lint.LocalUnused", json!(true))]),
+                layer("/p/.luaurc", &[("languageMode", json!("nocheck")), ("aliases.lib", json!("/p/lib"))]),
+                layer("/p/q/.luaurc", &[("languageMode", json!("nonstrict")), ("lint.*", json!(false))])
+            ]
+        );
+        assert_eq!(settings, [
+            Setting {
+                key: "aliases.lib".into(),
+                value: json!("/p/lib"),
+                source: "/p/.luaurc".into(),
+                overrides: vec![]
+            },
+            Setting {
+                key: "languageMode".into(),
+                value: json!("nonstrict"),
+                source: "/p/q/.luaurc".into(),
+                overrides: vec!["/.luaurc".into(), "/p/.luaurc".into()]
+            },
+            Setting {
+                key: "lint.*".into(),
+                value: json!(false),
+                source: "/p/q/.luaurc".into(),
+                overrides: vec![]
+            }
+        ]);
+    }
+
+    #[test]
+    fn command_line_limits_override_the_workspace() {
+        let dir = TempDir::with_files("config", &[(".ludorc", r#"{ "version": 2, "limits": { "timeout": "1s", "memory": "1KiB" } }"#)]);
+        let limits = Limits { memory: Some(2048), ..Limits::default() };
+        let (root, settings) = workspace_rc_settings(&dir, &limits).expect(".ludorc should load");
+        assert_eq!(root.as_deref(), Some(&*dir));
+        let keys: Vec<_> = settings.iter().map(|x| (x.key.as_str(), x.value.clone(), x.overrides.len())).collect();
+        assert_eq!(keys, [
+            ("limits.memory", json!(2048), 1),
+            ("limits.timeout", json!("1s"), 0),
+            ("version", json!(2), 0)
+        ]);
+        assert_eq!(settings[0].source, COMMAND_LINE);
+    }
+
+    #[test]
+    fn only_the_nearest_ludorc_declares_natives() {
+        let dir = TempDir::with_files("config-nested", &[
+            (".ludorc", r#"{
+                "version": 2,
+                "native": { "name": "outer", "parent": ".", "entryPoint": "luaopen_outer" },
+                "limits": { "timeout": "1s" }
+            }"#),
+            ("inner/.ludorc", r#"{ "version": 2, "permissions": { "@lib": { "native": true } } }"#)
+        ]);
+        let inner = dir.join("inner");
+        let (root, settings) = workspace_rc_settings(&inner, &Limits::default()).expect(".ludorc files should load");
+        assert_eq!(root.as_deref(), Some(inner.as_path()));
+        let keys: Vec<_> = settings.iter().map(|x| (x.key.as_str(), x.source.clone(), x.overrides.len())).collect();
+        assert_eq!(keys, [
+            ("limits.timeout", dir.join(".ludorc").display().to_string(), 0),
+            ("permissions.@lib.native", inner.join(".ludorc").display().to_string(), 0),
+            ("version", inner.join(".ludorc").display().to_string(), 1)
+        ]);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ansi_term::Color::{Blue, Red};
+use ansi_term::Style;
+
+/// Lines of source shown either side of the line an error points at.
+const SNIPPET_CONTEXT: usize = 1;
+
+/// Removes the colours and styles ansi_term adds, for output which isn't going to a terminal.
+pub fn strip_ansi(
+    text: &str
+) -> String {
+    let mut plain = String::new();
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => escaped = true,
+            'm' if escaped => escaped = false,
+            c if !escaped => plain.push(c),
+            _ => ()
+        }
+    }
+    plain
+}
+
+/// Finds the first place in an error message or traceback which points into one of the chunks in
+/// `chunk_paths`, returning its chunk name, file and line number.
+fn find_location<'a>(
+    text: &str,
+    chunk_paths: &'a HashMap<String, PathBuf>
+) -> Option<(&'a str, &'a Path, usize)> {
+    text.lines().find_map(|line| {
+        chunk_paths.iter()
+            .filter_map(|(name, path)| {
+                let index = line.match_indices(&format!("{name}:"))
+                    .map(|(index, _)| index)
+                    .find(|index| line[..*index].chars().last().is_none_or(|c| c.is_whitespace() || c == ':'))?;
+                let digits: String = line[index + name.len() + 1..].chars().take_while(char::is_ascii_digit).collect();
+                let line_number = digits.parse().ok()?;
+                Some((index, (name.as_str(), path.as_path(), line_number)))
+            })
+            .min_by_key(|(index, _)| *index)
+            .map(|(_, location)| location)
+    })
+}
+
+/// Renders the lines around `line_number` of `source`, highlighting that line. Given a 1-based
+/// byte `column`, a caret points at it; otherwise the whole line is underlined.
+pub fn render_snippet(
+    name: &str,
+    source: &str,
+    line_number: usize,
+    column: Option<usize>
+) -> Option<String> {
+    // Split rather than `lines`, so errors at the end of a file still have a line to point at,
+    // but only show the empty line after a final newline when that is where the error is.
+    let mut lines: Vec<_> = source.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    if lines.len() > 1 && lines.last() == Some(&"") && line_number < lines.len() {
+        lines.pop();
+    }
+    lines.get(line_number.checked_sub(1)?)?;
+    let first = line_number.saturating_sub(SNIPPET_CONTEXT).max(1);
+    let last = (line_number + SNIPPET_CONTEXT).min(lines.len());
+    let width = last.to_string().len();
+    let gutter = |number: &str| Blue.bold().paint(format!("{number:>width$} |")).to_string();
+    let location = match column {
+        Some(column) => format!("{name}:{line_number}:{column}"),
+        None => format!("{name}:{line_number}")
+    };
+
+    let mut snippet = vec![
+        format!("{}{} {location}", " ".repeat(width), Blue.bold().paint("-->")),
+        gutter("")
+    ];
+    for number in first..=last {
+        let text = lines[number - 1];
+        if number == line_number {
+            snippet.push(format!("{} {}", gutter(&number.to_string()), Style::new().bold().paint(text)));
+            let (indent, underline) = match column {
+                Some(column) => {
+                    let before = text.get(..column.saturating_sub(1)).unwrap_or(text);
+                    let indent: String = before.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                    (indent, "^".to_string())
+                },
+                None => {
+                    let indent = &text[..text.len() - text.trim_start().len()];
+                    (indent.to_string(), "^".repeat(text.trim().chars().count().max(1)))
+                }
+            };
+            snippet.push(format!("{} {indent}{}", gutter(""), Red.bold().paint(underline)));
+        } else {
+            snippet.push(format!("{} {text}", gutter(&number.to_string())));
+        }
+    }
+    Some(snippet.join("\n"))
+}
+
+/// Formats an uncaught Luau error with its traceback, followed by the source line it was raised
+/// from when that can be found.
+pub fn format_lua_error(
+    err: &mlua::Error,
+    chunk_paths: &HashMap<String, PathBuf>
+) -> String {
+    let text = err.to_string();
+    let text = text.strip_prefix("runtime error: ").unwrap_or(&text);
+    let (message, traceback) = match text.split_once("\nstack traceback:") {
+        Some((message, traceback)) => (message, Some(traceback)),
+        None => (text, None)
+    };
+
+    let mut formatted = vec![Red.bold().paint(message).to_string()];
+    if let Some(traceback) = traceback {
+        formatted.push(Style::new().dimmed().paint("stack traceback:").to_string());
+        formatted.extend(traceback.lines()
+            // mlua marks where a Rust callback's own traceback starts with `>`.
+            .map(|line| line.trim().trim_start_matches('>'))
+            .filter(|line| !line.is_empty())
+            .map(|line| format!("    {}", Style::new().dimmed().paint(line))));
+    }
+    let snippet = find_location(text, chunk_paths).and_then(|(name, path, line_number)| {
+        let source = fs::read_to_string(path).ok()?;
+        render_snippet(name, &source, line_number, None)
+    });
+    if let Some(snippet) = snippet {
+        formatted.push(String::new());
+        formatted.push(snippet);
+    }
+    formatted.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_location_of_known_chunks() {
+        let chunk_paths = HashMap::from([
+            ("main.luau".to_string(), PathBuf::from("/p/main.luau")),
+            ("@tool/init.luau".to_string(), PathBuf::from("/p/tool/init.luau"))
+        ]);
+        let text = "[string \"x\"]:1: oops\nstack traceback:\n\t@tool/init.luau:12 function f\n\tmain.luau:3";
+        assert_eq!(find_location(text, &chunk_paths), Some(("@tool/init.luau", Path::new("/p/tool/init.luau"), 12)));
+        assert_eq!(find_location("lib/main.luau:4: oops", &chunk_paths), None, "Only whole chunk names should match");
+    }
+
+    #[test]
+    fn snippet_highlights_the_offending_line() {
+        let snippet = render_snippet("main.luau", "local a = 1\n  error(a)\nreturn a\n", 2, None).unwrap();
+        let plain = strip_ansi(&snippet);
+        assert_eq!(plain.lines().collect::<Vec<_>>(), [
+            " --> main.luau:2",
+            "  |",
+            "1 | local a = 1",
+            "2 |   error(a)",
+            "  |   ^^^^^^^^",
+            "3 | return a"
+        ]);
+        let last = strip_ansi(&render_snippet("main.luau", "local a = 1\nreturn a\n", 2, None).unwrap());
+        assert!(last.ends_with("2 | return a\n  | ^^^^^^^^"), "No gutter line after the final newline: {last:?}");
+        let end = strip_ansi(&render_snippet("main.luau", "local a = (\n", 2, Some(1)).unwrap());
+        assert!(end.ends_with("2 | \n  | ^"), "Errors at the end of the file still point there: {end:?}");
+        assert_eq!(render_snippet("main.luau", "return 1", 5, None), None);
+
+        let caret = strip_ansi(&render_snippet(".ludorc", "{\n\t\"version\": x\n}", 2, Some(13)).unwrap());
+        assert_eq!(caret.lines().collect::<Vec<_>>(), [
+            " --> .ludorc:2:13",
+            "  |",
+            "1 | {",
+            "2 | \t\"version\": x",
+            "  | \t           ^",
+            "3 | }"
+        ]);
+    }
+}
@@ -0,0 +1,210 @@
+use clap::ValueEnum;
+use serde_json::json;
+use thiserror::Error;
+use crate::diagnostics::strip_ansi;
+use crate::limits::LimitExceeded;
+use crate::trust::NotBlessedError;
+
+/// Exit code used for failures which don't have a kind of their own.
+const EXIT_FAILURE: u8 = 1;
+
+/// What went wrong, with a stable exit code and name for wrappers to match on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Config,
+    Resolution,
+    PermissionDenied,
+    NotBlessed,
+    NativeLoad,
+    Runtime,
+    MemoryLimit,
+    Timeout,
+    InstructionLimit
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Config => "config",
+            Self::Resolution => "resolution",
+            Self::PermissionDenied => "permission_denied",
+            Self::NotBlessed => "not_blessed",
+            Self::NativeLoad => "native_load",
+            Self::Runtime => "runtime",
+            Self::MemoryLimit => "memory_limit",
+            Self::Timeout => "timeout",
+            Self::InstructionLimit => "instruction_limit"
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::NotBlessed => 3,
+            Self::MemoryLimit => 4,
+            Self::Timeout => 5,
+            Self::InstructionLimit => 6,
+            Self::Config => 7,
+            Self::Resolution => 8,
+            Self::PermissionDenied => 9,
+            Self::NativeLoad => 10,
+            Self::Runtime => 11
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LudoError {
+    /// A .ludorc or .luaurc which couldn't be read, or doesn't make sense.
+    #[error("{0}")]
+    Config(String),
+    /// A module or alias which couldn't be found, or a module which requires itself.
+    #[error("{0}")]
+    Resolution(String),
+    /// Something a workspace hasn't been granted permission to do.
+    #[error("{0}")]
+    PermissionDenied(String),
+    #[error(transparent)]
+    NotBlessed(#[from] NotBlessedError),
+    /// A native library which couldn't be read, opened or initialised.
+    #[error("{0}")]
+    NativeLoad(String),
+    /// An error raised by a script, along with its traceback.
+    #[error("{0}")]
+    Runtime(String),
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded)
+}
+
+impl LudoError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Config(_) => ErrorKind::Config,
+            Self::Resolution(_) => ErrorKind::Resolution,
+            Self::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            Self::NotBlessed(_) => ErrorKind::NotBlessed,
+            Self::NativeLoad(_) => ErrorKind::NativeLoad,
+            Self::Runtime(_) => ErrorKind::Runtime,
+            Self::LimitExceeded(LimitExceeded::Memory(_)) => ErrorKind::MemoryLimit,
+            Self::LimitExceeded(LimitExceeded::Timeout(_)) => ErrorKind::Timeout,
+            Self::LimitExceeded(LimitExceeded::Instructions(_)) => ErrorKind::InstructionLimit
+        }
+    }
+
+    /// Makes an error of `kind` from a message. Kinds which carry more than a message, like
+    /// `NotBlessed`, never pass through a running script, so they're reported as runtime errors.
+    pub fn of_kind(
+        kind: ErrorKind,
+        message: String
+    ) -> Self {
+        match kind {
+            ErrorKind::Config => Self::Config(message),
+            ErrorKind::Resolution => Self::Resolution(message),
+            ErrorKind::PermissionDenied => Self::PermissionDenied(message),
+            ErrorKind::NativeLoad => Self::NativeLoad(message),
+            _ => Self::Runtime(message)
+        }
+    }
+
+    /// Flattens `err` and its causes into an error of `kind`, unless one of its causes already has
+    /// a more specific kind.
+    pub fn wrap(
+        kind: ErrorKind,
+        err: anyhow::Error
+    ) -> Self {
+        let kind = error_kind(&err).unwrap_or(kind);
+        Self::of_kind(kind, format!("{err:#}"))
+    }
+}
+
+impl From<LudoError> for mlua::Error {
+    fn from(
+        err: LudoError
+    ) -> Self {
+        mlua::Error::external(err)
+    }
+}
+
+/// Finds the kind of the outermost `LudoError` behind `err`, if any.
+pub fn error_kind(
+    err: &anyhow::Error
+) -> Option<ErrorKind> {
+    err.chain().find_map(|x| x.downcast_ref::<LudoError>()).map(LudoError::kind)
+}
+
+/// Finds the kind of the `LudoError` which stopped a script, if it was one, even after the error
+/// passed through Luau.
+pub fn lua_error_kind(
+    err: &mlua::Error
+) -> Option<ErrorKind> {
+    err.chain().find_map(|x| x.downcast_ref::<LudoError>()).map(LudoError::kind)
+}
+
+/// Converts an error from Rust code called by Luau, keeping its causes in the message and its
+/// kind, if it has one, for when it reaches the top of the script.
+pub fn lua_error(
+    err: anyhow::Error
+) -> mlua::Error {
+    let message = format!("{err:#}");
+    match error_kind(&err) {
+        Some(kind) => mlua::Error::external(LudoError::of_kind(kind, message)),
+        None => mlua::Error::runtime(message)
+    }
+}
+
+/// How errors which stop Ludo are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// A human-readable message, with the error's code
+    #[default]
+    Text,
+    /// A single line of JSON with the error's `code`, `exit_code` and `message`
+    Json
+}
+
+/// Prints an error which stopped Ludo to standard error, returning the exit code to stop with.
+pub fn report_error(
+    err: &anyhow::Error,
+    format: ErrorFormat
+) -> u8 {
+    let kind = error_kind(err);
+    let code = kind.map_or("error", |kind| kind.code());
+    let exit_code = kind.map_or(EXIT_FAILURE, |kind| kind.exit_code());
+    match format {
+        ErrorFormat::Text => match kind {
+            Some(_) => eprintln!("Error [{code}]: {err:?}"),
+            None => eprintln!("Error: {err:?}")
+        },
+        ErrorFormat::Json => {
+            let report = json!({ "code": code, "exit_code": exit_code, "message": strip_ansi(&format!("{err:#}")) });
+            eprintln!("{report}");
+        }
+    }
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use mlua::Lua;
+
+    #[test]
+    fn kinds_survive_context_and_luau() {
+        let err = anyhow::Error::new(LudoError::Resolution("No module found".into())).context("Failed to require");
+        assert_eq!(error_kind(&err), Some(ErrorKind::Resolution));
+        assert_eq!(LudoError::wrap(ErrorKind::Config, err).kind(), ErrorKind::Resolution);
+
+        let lua = Lua::new();
+        let fail = lua.create_function(|_, ()| -> mlua::Result<()> {
+            Err(lua_error(anyhow::Error::new(LudoError::PermissionDenied("Permission denied".into())).context("Failed to require")))
+        }).unwrap();
+        lua.globals().set("fail", fail).unwrap();
+        let err = lua.load("local function inner() fail() end inner()").exec().unwrap_err();
+        assert_eq!(lua_error_kind(&err), Some(ErrorKind::PermissionDenied));
+        assert!(err.to_string().contains("Failed to require: Permission denied"));
+
+        let untyped = lua.load("error('oops')").exec().unwrap_err();
+        assert_eq!(lua_error_kind(&untyped), None);
+        assert_eq!(error_kind(&Err::<(), _>(anyhow::anyhow!("oops")).context("while testing").unwrap_err()), None);
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+use anyhow::{bail, Context, Result};
+use mlua::{Lua, Table};
+use crate::error::{lua_error, LudoError};
+use crate::fs_util::canonicalise_path;
+use crate::permissions::FsAccess;
+
+/// Name scripts require the filesystem library by.
+pub const FS_MODULE: &str = "@ludo/fs";
+
+/// Resolves a path given by a script, failing unless the workspace may use it. Relative paths are
+/// relative to the current directory. When `follow_link` is unset, a symlink at the path itself is
+/// not followed, so it can be removed or renamed without touching what it points to.
+fn resolve(
+    access: &FsAccess,
+    path: &str,
+    follow_link: bool
+) -> Result<PathBuf> {
+    let path = Path::new(path);
+    let resolved = match (follow_link, path.file_name()) {
+        (false, Some(file_name)) => {
+            let parent = path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            canonicalise_path(parent)?.join(file_name)
+        },
+        _ => canonicalise_path(path)?
+    };
+    if !access.allows(&resolved) {
+        bail!(LudoError::PermissionDenied(format!("Permission denied for {}, which hasn't been granted to this workspace", resolved.display())));
+    }
+    Ok(resolved)
+}
+
+/// Wraps a filesystem operation as a Luau function taking a path as its first argument.
+fn create_operation<A: mlua::FromLuaMulti, R: mlua::IntoLuaMulti>(
+    lua: &Lua,
+    access: &Rc<FsAccess>,
+    follow_link: bool,
+    operation: impl Fn(&Lua, PathBuf, A) -> mlua::Result<R> + 'static
+) -> mlua::Result<mlua::Function> {
+    let access = access.clone();
+    lua.create_function(move |lua, (path, args): (String, A)| {
+        let resolved = resolve(&access, &path, follow_link).map_err(lua_error)?;
+        operation(lua, resolved, args)
+    })
+}
+
+fn stat(
+    lua: &Lua,
+    path: PathBuf,
+    _: ()
+) -> mlua::Result<Table> {
+    let metadata = fs::symlink_metadata(&path)
+        .with_context(|| format!("Failed to read metadata of {}", path.display()))
+        .map_err(lua_error)?;
+    let kind = if metadata.is_symlink() { "symlink" } else if metadata.is_dir() { "dir" } else { "file" };
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs_f64());
+    let result = lua.create_table()?;
+    result.set("kind", kind)?;
+    result.set("size", metadata.len())?;
+    result.set("modified", modified)?;
+    result.set("readonly", metadata.permissions().readonly())?;
+    Ok(result)
+}
+
+/// Creates the `@ludo/fs` library, limited to the parts of the filesystem in `access`.
+pub fn create_fs_library(
+    lua: &Lua,
+    access: FsAccess
+) -> mlua::Result<Table> {
+    let access = Rc::new(access);
+    let library = lua.create_table()?;
+
+    library.set("read", create_operation(lua, &access, true, |lua, path, ()| {
+        let contents = fs::read(&path).with_context(|| format!("Failed to read {}", path.display())).map_err(lua_error)?;
+        lua.create_string(contents)
+    })?)?;
+    library.set("write", create_operation(lua, &access, true, |_, path, contents: mlua::String| {
+        fs::write(&path, contents.as_bytes()).with_context(|| format!("Failed to write {}", path.display())).map_err(lua_error)
+    })?)?;
+    library.set("list", create_operation(lua, &access, true, |_, path, ()| {
+        let mut names = fs::read_dir(&path)
+            .with_context(|| format!("Failed to list {}", path.display()))
+            .map_err(lua_error)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Failed to list {}", path.display()))
+            .map_err(lua_error)?;
+        names.sort();
+        Ok(names)
+    })?)?;
+    library.set("stat", create_operation(lua, &access, false, stat)?)?;
+    library.set("exists", create_operation(lua, &access, false, |_, path, ()| Ok(path.symlink_metadata().is_ok()))?)?;
+    library.set("mkdir", create_operation(lua, &access, true, |_, path, recursive: Option<bool>| {
+        let result = if recursive.unwrap_or(false) { fs::create_dir_all(&path) } else { fs::create_dir(&path) };
+        result.with_context(|| format!("Failed to create directory {}", path.display())).map_err(lua_error)
+    })?)?;
+    library.set("remove", create_operation(lua, &access, false, |_, path, recursive: Option<bool>| {
+        let metadata = fs::symlink_metadata(&path).with_context(|| format!("Failed to remove {}", path.display())).map_err(lua_error)?;
+        let result = match (metadata.is_dir(), recursive.unwrap_or(false)) {
+            (true, true) => fs::remove_dir_all(&path),
+            (true, false) => fs::remove_dir(&path),
+            (false, _) => fs::remove_file(&path)
+        };
+        result.with_context(|| format!("Failed to remove {}", path.display())).map_err(lua_error)
+    })?)?;
+    let rename_access = access.clone();
+    library.set("rename", create_operation(lua, &access, false, move |_, from, to: String| {
+        let to = resolve(&rename_access, &to, false).map_err(lua_error)?;
+        fs::rename(&from, &to).with_context(|| format!("Failed to rename {} to {}", from.display(), to.display())).map_err(lua_error)
+    })?)?;
+
+    library.set_readonly(true);
+    Ok(library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+
+    #[test]
+    fn directory_access_rejects_escapes() {
+        let dir = TempDir::new("fs-access");
+        fs::create_dir_all(dir.join("granted")).unwrap();
+        let granted = dir.join("granted");
+        let access = FsAccess::Directories(vec![granted.clone()]);
+        let inside = dir.join("granted/file.txt");
+        assert_eq!(resolve(&access, inside.to_str().unwrap(), true).unwrap(), granted.join("file.txt"));
+        let escape = dir.join("granted/../secret.txt");
+        assert!(resolve(&access, escape.to_str().unwrap(), true).is_err(), "../ should not escape granted directories");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_access_rejects_symlink_escapes() {
+        let dir = TempDir::with_files("fs-symlink", &[("secret.txt", "secret"), ("granted/file.txt", "")]);
+        std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("granted/link")).unwrap();
+        let access = FsAccess::Directories(vec![dir.join("granted")]);
+        let link = dir.join("granted/link");
+        assert!(resolve(&access, link.to_str().unwrap(), true).is_err(), "Symlinks should not lead outside granted directories");
+        assert!(resolve(&access, link.to_str().unwrap(), false).is_ok(), "The symlink itself is inside the granted directory");
+    }
+}
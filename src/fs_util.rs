@@ -1,7 +1,8 @@
 use std::ffi::OsStr;
 use anyhow::{bail, Context, Result};
 use std::fs::File;
-use std::{fs, io};
+use std::io::Write;
+use std::{env, fs, io, process};
 use std::path::{Component, Path, PathBuf};
 use libloading::library_filename;
 use crate::luaurc::CanonicalLuauRc;
@@ -18,6 +19,84 @@ pub fn open_file_if_exists(
     }
 }
 
+pub fn read_file_if_exists(
+    path: &Path
+) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(source) => Ok(Some(source)),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(None),
+            _ => Err(err.into())
+        }
+    }
+}
+
+/// Replaces the contents of a file in one step, so readers never observe a partial write.
+pub fn write_file_atomically(
+    path: &Path,
+    contents: &[u8]
+) -> Result<()> {
+    let parent = path.parent().context("File must exist inside of a directory")?;
+    let file_name = path.file_name().context("File must have a name")?;
+    let temp_path = parent.join(format!(".{}.{}.tmp", file_name.to_string_lossy(), process::id()));
+    let result = (|| -> Result<()> {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Expresses `path` relative to the directory `base`, using `..` where the two diverge.
+/// Both paths should be absolute.
+pub fn relative_path(
+    base: &Path,
+    path: &Path
+) -> PathBuf {
+    let base_components: Vec<_> = base.components().collect();
+    let path_components: Vec<_> = path.components().collect();
+    let common = base_components.iter().zip(&path_components).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push(Component::ParentDir);
+    }
+    for component in &path_components[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+/// Makes a path absolute and canonical, even if it doesn't exist yet. `.` and `..` components are
+/// resolved first, then the deepest ancestor which exists is canonicalised, following symlinks.
+pub fn canonicalise_path(
+    path: &Path
+) -> Result<PathBuf> {
+    let path = env::current_dir().context("No current working directory found")?.join(path);
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => if !normalised.pop() { bail!("Path {} escapes the root directory", path.display()) },
+            component => normalised.push(component)
+        }
+    }
+    let mut missing = vec![];
+    let mut existing = normalised.as_path();
+    while existing.symlink_metadata().is_err() {
+        missing.push(existing.file_name().context("Path has no existing ancestor")?);
+        existing = existing.parent().context("Path has no existing ancestor")?;
+    }
+    let mut canonical = existing.canonicalize()
+        .with_context(|| format!("Failed to canonicalise {}", existing.display()))?;
+    canonical.extend(missing.into_iter().rev());
+    Ok(canonical)
+}
+
 pub fn resolve_module_path(
     luau_rc: &CanonicalLuauRc,
     script_location: &Path,
@@ -60,7 +139,10 @@ pub fn locate_luau_script(
     script_path: &Path
 ) -> Result<Option<PathBuf>> {
     for extension in ["luau", "lua"].into_iter() {
-        let script_path = script_path.with_extension(extension);
+        // Appended rather than substituted, so module names may contain dots.
+        let mut script_path = script_path.as_os_str().to_owned();
+        script_path.push(format!(".{extension}"));
+        let script_path = PathBuf::from(script_path);
         if script_path.is_file() {
             return Ok(Some(script_path));
         }
@@ -75,6 +157,53 @@ pub fn select_native_binary(
     parent.join(library_filename(name))
 }
 
+/// A scratch directory for a test, named after it and removed again once the test is done.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Creates an empty directory, replacing any left behind by an earlier run.
+    pub fn new(
+        name: &str
+    ) -> Self {
+        let path = env::temp_dir().join(format!("ludo-test-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("Temporary directory should be created");
+        Self(path.canonicalize().expect("Temporary directory should canonicalise"))
+    }
+
+    /// Creates a directory holding `files`, given by their path within it.
+    pub fn with_files(
+        name: &str,
+        files: &[(impl AsRef<Path>, &str)]
+    ) -> Self {
+        let dir = Self::new(name);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).expect("Temporary directory should be created");
+            fs::write(path, contents).expect("Temporary file should be written");
+        }
+        dir
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,6 +214,7 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("")
@@ -98,6 +228,7 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("/foo/bar/baz")
@@ -111,6 +242,7 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("C:/foo/bar/baz")
@@ -124,6 +256,7 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("./foo")
@@ -137,6 +270,7 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("../foo")
@@ -150,6 +284,7 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [(String::from("hello"), PathBuf::from("different/path"))].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("@hello")
@@ -163,6 +298,7 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [(String::from("hello"), PathBuf::from("different/path"))].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("@hello/@world")
@@ -176,10 +312,54 @@ mod tests {
         let result = resolve_module_path(
             &CanonicalLuauRc {
                 aliases: [(String::from("hello"), PathBuf::from("different/path"))].into_iter().collect(),
+                ..CanonicalLuauRc::default()
             },
             script_location,
             Path::new("@hello/foo/bar")
         ).expect("Alias should not error");
         assert_eq!(result, Path::new("different/path/foo/bar"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn relative_path_inside_base() {
+        let result = relative_path(Path::new("/root/project"), Path::new("/root/project/lib/foo.so"));
+        assert_eq!(result, Path::new("lib/foo.so"));
+    }
+
+    #[test]
+    fn relative_path_outside_base() {
+        let result = relative_path(Path::new("/root/project/scripts"), Path::new("/root/lib/foo.so"));
+        assert_eq!(result, Path::new("../../lib/foo.so"));
+    }
+
+    #[test]
+    fn locate_module_script_order() {
+        let dir = TempDir::new("locate-module");
+        fs::create_dir_all(dir.join("module")).unwrap();
+        let module_path = dir.join("module");
+        for (file, expected) in [
+            ("module/init.lua", "module/init.lua"),
+            ("module/init.luau", "module/init.luau"),
+            ("module.lua", "module.lua"),
+            ("module.luau", "module.luau")
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+            let located = locate_module_script(&module_path).expect("Locating should not error");
+            assert_eq!(located, Some(dir.join(expected)), "{expected} should take precedence");
+        }
+    }
+
+    #[test]
+    fn locate_module_script_with_dots() {
+        let dir = TempDir::with_files("locate-dots", &[("module.test.luau", "")]);
+        let located = locate_module_script(&dir.join("module.test")).expect("Locating should not error");
+        assert_eq!(located, Some(dir.join("module.test.luau")));
+    }
+
+    #[test]
+    fn canonicalise_missing_path() {
+        let dir = TempDir::new("canonicalise");
+        let result = canonicalise_path(&dir.join("missing/../other/./file")).expect("Missing paths should canonicalise");
+        assert_eq!(result, dir.join("other/file"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use crate::diagnostics::render_snippet;
+
+/// A configuration file which couldn't be decoded, along with a snippet pointing at the offending
+/// token.
+#[derive(Debug)]
+pub struct DecodeError {
+    error: serde_json::Error,
+    snippet: Option<String>
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>
+    ) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n{snippet}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Blanks out comments and trailing commas, so what's left is plain JSON. Everything removed is
+/// replaced by spaces, keeping line breaks, so positions in the result match those in `source`.
+fn strip_jsonc(
+    source: &str
+) -> String {
+    let mut bytes = source.as_bytes().to_vec();
+    let blank = |bytes: &mut [u8], start: usize, end: usize| {
+        for byte in &mut bytes[start..end] {
+            if *byte != b'\n' && *byte != b'\r' {
+                *byte = b' ';
+            }
+        }
+    };
+    let mut pending_comma = None;
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'"' => {
+                index += 1;
+                while index < bytes.len() && bytes[index] != b'"' {
+                    index += if bytes[index] == b'\\' { 2 } else { 1 };
+                }
+                pending_comma = None;
+            },
+            b'/' if bytes.get(index + 1) == Some(&b'/') => {
+                let end = bytes[index..].iter().position(|&x| x == b'\n').map_or(bytes.len(), |x| index + x);
+                blank(&mut bytes, index, end);
+                index = end;
+                continue;
+            },
+            b'/' if bytes.get(index + 1) == Some(&b'*') => {
+                // An unterminated comment is left for the JSON parser to point at.
+                let Some(length) = source[index + 2..].find("*/") else { break };
+                let end = index + 2 + length + 2;
+                blank(&mut bytes, index, end);
+                index = end;
+                continue;
+            },
+            b'}' | b']' => {
+                if let Some(comma) = pending_comma.take() {
+                    bytes[comma] = b' ';
+                }
+            },
+            b',' => pending_comma = Some(index),
+            byte if byte.is_ascii_whitespace() => (),
+            _ => pending_comma = None
+        }
+        index += 1;
+    }
+    // Only ASCII outside of strings was replaced, by ASCII, so this is still valid UTF-8.
+    String::from_utf8(bytes).expect("Stripping comments should keep the source valid UTF-8")
+}
+
+/// Decodes JSON with comments and trailing commas, as the Luau tooling accepts in .luaurc files.
+/// `path` names the file in errors.
+pub fn from_jsonc<T: DeserializeOwned>(
+    source: &str,
+    path: &Path
+) -> Result<T, DecodeError> {
+    serde_json::from_str(&strip_jsonc(source)).map_err(|error| {
+        let column = Some(error.column().max(1));
+        let snippet = render_snippet(&path.display().to_string(), source, error.line(), column);
+        DecodeError { error, snippet }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn comments_and_trailing_commas_are_accepted() {
+        let source = r#"{
+            // Line comments,
+            "aliases": { "lib": "./lib", }, /* block comments, */
+            "globals": [ "a", "b//c", "d/*e*/", ],
+            "escaped": "quote\" // not a comment",
+        }"#;
+        let value: Value = from_jsonc(source, Path::new(".luaurc")).unwrap();
+        assert_eq!(value, json!({
+            "aliases": { "lib": "./lib" },
+            "globals": ["a", "b//c", "d/*e*/"],
+            "escaped": "quote\" // not a comment"
+        }));
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let source = "{\n    // The version\n    \"version\": 1,\n    \"native\" true\n}\n";
+        let err = from_jsonc::<Value>(source, Path::new("/p/.ludorc")).unwrap_err();
+        assert_eq!((err.error.line(), err.error.column()), (4, 14));
+        let message = crate::diagnostics::strip_ansi(&err.to_string());
+        assert!(message.contains("--> /p/.ludorc:4:14"), "{message}");
+        assert!(message.contains("4 |     \"native\" true\n  |              ^"), "{message}");
+    }
+
+    #[test]
+    fn unterminated_comments_are_errors() {
+        assert!(from_jsonc::<Value>("{ /* \"version\": 1 }", Path::new(".ludorc")).is_err());
+    }
+}
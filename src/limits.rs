@@ -0,0 +1,289 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use anyhow::{bail, Context, Result};
+use mlua::{Lua, VmState};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use thiserror::Error;
+use crate::process::ExitRequested;
+
+/// Bounds on what a single run of a script may consume. Only the main script's .ludorc sets
+/// these, and options given on the command line take precedence over it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// The most memory the Luau heap may use, in bytes.
+    #[serde(default, deserialize_with = "deserialize_memory", skip_serializing_if = "Option::is_none")]
+    pub memory: Option<usize>,
+    /// How long the script and the tasks it schedules may run for.
+    #[serde(default, deserialize_with = "deserialize_timeout", serialize_with = "serialize_timeout", skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<Duration>,
+    /// How many times Luau may check in with the runtime, which it does on every function call
+    /// and loop iteration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<u64>
+}
+
+impl Limits {
+    /// Fills in any limits this doesn't set from `fallback`.
+    pub fn or(
+        self,
+        fallback: Limits
+    ) -> Self {
+        Self {
+            memory: self.memory.or(fallback.memory),
+            timeout: self.timeout.or(fallback.timeout),
+            instructions: self.instructions.or(fallback.instructions)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Every limit set here, named by its path in a .ludorc, like `limits.timeout`.
+    pub fn settings(&self) -> Vec<(String, Value)> {
+        let mut settings = vec![];
+        if let Some(memory) = self.memory {
+            settings.push(("limits.memory".into(), json!(memory)));
+        }
+        if let Some(timeout) = self.timeout {
+            settings.push(("limits.timeout".into(), json!(humantime::format_duration(timeout).to_string())));
+        }
+        if let Some(instructions) = self.instructions {
+            settings.push(("limits.instructions".into(), json!(instructions)));
+        }
+        settings
+    }
+}
+
+/// Parses a number of bytes with an optional unit, like `512KB` or `64MiB`.
+pub fn parse_memory_size(
+    text: &str
+) -> Result<usize> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: usize = number.parse().with_context(|| format!("Invalid memory size {text:?}"))?;
+    let multiplier: usize = match unit.trim() {
+        "" | "B" => 1,
+        "KB" => 1000,
+        "KiB" => 1 << 10,
+        "MB" => 1000 * 1000,
+        "MiB" => 1 << 20,
+        "GB" => 1000 * 1000 * 1000,
+        "GiB" => 1 << 30,
+        unit => bail!("Unknown memory unit {unit:?} in {text:?}, expected one of B, KB, KiB, MB, MiB, GB or GiB")
+    };
+    number.checked_mul(multiplier).with_context(|| format!("Memory size {text:?} is too large"))
+}
+
+fn deserialize_memory<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<usize>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Memory {
+        Bytes(usize),
+        Text(String)
+    }
+    match Memory::deserialize(deserializer)? {
+        Memory::Bytes(bytes) => Ok(Some(bytes)),
+        Memory::Text(text) => parse_memory_size(&text).map(Some).map_err(serde::de::Error::custom)
+    }
+}
+
+fn deserialize_timeout<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<Duration>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn serialize_timeout<S: Serializer>(
+    timeout: &Option<Duration>,
+    serializer: S
+) -> Result<S::Ok, S::Error> {
+    match timeout {
+        Some(timeout) => serializer.serialize_str(&humantime::format_duration(*timeout).to_string()),
+        None => serializer.serialize_none()
+    }
+}
+
+/// Returned when a script is stopped for exceeding one of its limits.
+#[derive(Error, Debug, Clone, Copy, PartialEq)]
+pub enum LimitExceeded {
+    #[error("Script exceeded its memory limit of {0} bytes (set by --max-memory or limits.memory)")]
+    Memory(usize),
+    #[error("Script exceeded its timeout of {} (set by --timeout or limits.timeout)", humantime::format_duration(*.0))]
+    Timeout(Duration),
+    #[error("Script exceeded its instruction budget of {0} (set by --max-instructions or limits.instructions)")]
+    Instructions(u64)
+}
+
+fn is_memory_error(
+    err: &mlua::Error
+) -> bool {
+    match err {
+        mlua::Error::MemoryError(_) => true,
+        mlua::Error::CallbackError { cause, .. } | mlua::Error::WithContext { cause, .. } => is_memory_error(cause),
+        _ => false
+    }
+}
+
+/// Enforces `Limits` on a Luau state.
+pub struct Limiter {
+    limits: Limits,
+    deadline: Cell<Option<Instant>>,
+    instructions: Cell<u64>,
+    exceeded: Cell<Option<LimitExceeded>>
+}
+
+impl Limiter {
+    /// Applies `limits` to `lua`. The timeout and instruction budget only start counting once
+    /// `start` is called. The interrupt this installs also stops a script which called
+    /// `process.exit`, however often it catches the error doing so raises.
+    pub fn install(
+        lua: &Lua,
+        limits: Limits
+    ) -> mlua::Result<Rc<Self>> {
+        let limiter = Rc::new(Self {
+            limits,
+            deadline: Cell::new(None),
+            instructions: Cell::new(0),
+            exceeded: Cell::new(None)
+        });
+        if let Some(memory) = limits.memory {
+            lua.set_memory_limit(memory)?;
+        }
+        let interrupt_limiter = limiter.clone();
+        lua.set_interrupt(move |lua| {
+            ExitRequested::check(lua)?;
+            match interrupt_limiter.check() {
+                Ok(()) => Ok(VmState::Continue),
+                Err(limit) => Err(mlua::Error::external(limit))
+            }
+        });
+        Ok(limiter)
+    }
+
+    /// Starts the timeout and instruction budget afresh.
+    pub fn start(&self) {
+        self.deadline.set(self.limits.timeout.map(|timeout| Instant::now() + timeout));
+        self.instructions.set(0);
+        self.exceeded.set(None);
+    }
+
+    /// When the current run times out, if it has a timeout.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.get()
+    }
+
+    /// Called by Luau as it runs. Once a limit is exceeded this keeps failing, so a script can't
+    /// carry on by catching the error with `pcall`.
+    fn check(&self) -> Result<(), LimitExceeded> {
+        if let Some(limit) = self.exceeded.get() {
+            return Err(limit);
+        }
+        let instructions = self.instructions.get() + 1;
+        self.instructions.set(instructions);
+        let exceeded = match (self.limits.instructions, self.limits.timeout) {
+            (Some(budget), _) if instructions > budget => LimitExceeded::Instructions(budget),
+            (_, Some(timeout)) if self.deadline.get().is_some_and(|x| Instant::now() >= x) => LimitExceeded::Timeout(timeout),
+            _ => return Ok(())
+        };
+        self.exceeded.set(Some(exceeded));
+        Err(exceeded)
+    }
+
+    /// Finds which limit, if any, stopped a run that ended with `error`. A timeout or instruction
+    /// budget exceeded during the run is reported even if the script caught the error it raised,
+    /// but a memory error is only reported if it ended the run, as the allocator can't tell us
+    /// about failed allocations a script recovered from.
+    pub fn exceeded(
+        &self,
+        error: Option<&mlua::Error>
+    ) -> Option<LimitExceeded> {
+        if let Some(limit) = self.exceeded.get() {
+            return Some(limit);
+        }
+        let error = error?;
+        if let Some(memory) = self.limits.memory.filter(|_| is_memory_error(error)) {
+            return Some(LimitExceeded::Memory(memory));
+        }
+        let timed_out = self.deadline.get().is_some_and(|x| Instant::now() >= x);
+        self.limits.timeout.filter(|_| timed_out).map(LimitExceeded::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::MultiValue;
+    use crate::task::{create_task_library, run_to_completion, Scheduler};
+
+    fn run(
+        limits: Limits,
+        source: &str
+    ) -> Option<LimitExceeded> {
+        let lua = Lua::new();
+        let scheduler = Scheduler::new();
+        lua.globals().set("task", create_task_library(&lua, &scheduler).unwrap()).unwrap();
+        let limiter = Limiter::install(&lua, limits).unwrap();
+        limiter.start();
+        scheduler.borrow_mut().set_deadline(limiter.deadline());
+        let function = lua.load(source).into_function().unwrap();
+        let result = run_to_completion(&lua, &scheduler, function, MultiValue::new());
+        limiter.exceeded(result.as_ref().err())
+    }
+
+    #[test]
+    fn parse_memory_sizes() {
+        assert_eq!(parse_memory_size("1024").unwrap(), 1024);
+        assert_eq!(parse_memory_size("64MiB").unwrap(), 64 << 20);
+        assert_eq!(parse_memory_size("2 GB").unwrap(), 2_000_000_000);
+        assert!(parse_memory_size("12 parsecs").is_err());
+        assert!(parse_memory_size("MiB").is_err());
+    }
+
+    #[test]
+    fn limits_decode_from_ludorc() {
+        let limits: Limits = serde_json::from_str(r#"{ "memory": "16MiB", "timeout": "1m 30s", "instructions": 1000 }"#).unwrap();
+        assert_eq!(limits, Limits {
+            memory: Some(16 << 20),
+            timeout: Some(Duration::from_secs(90)),
+            instructions: Some(1000)
+        });
+    }
+
+    #[test]
+    fn instruction_budget_cannot_be_caught() {
+        let limits = Limits { instructions: Some(10_000), ..Limits::default() };
+        let exceeded = run(limits, "while true do pcall(function() while true do end end) end");
+        assert_eq!(exceeded, Some(LimitExceeded::Instructions(10_000)));
+    }
+
+    #[test]
+    fn timeout_stops_busy_loops_and_waiting_tasks() {
+        let limits = Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() };
+        let timeout = Some(LimitExceeded::Timeout(Duration::from_millis(20)));
+        assert_eq!(run(limits, "while true do end"), timeout);
+        assert_eq!(run(limits, "task.wait(10)"), timeout);
+        assert_eq!(run(limits, "task.wait(0.001)"), None);
+    }
+
+    #[test]
+    fn memory_limit_stops_allocation() {
+        let limits = Limits { memory: Some(4 << 20), ..Limits::default() };
+        let exceeded = run(limits, "local t = {} for i = 1, 1e7 do t[i] = string.rep('x', 64) .. i end");
+        assert_eq!(exceeded, Some(LimitExceeded::Memory(4 << 20)));
+    }
+
+    #[test]
+    fn caught_memory_errors_leave_the_script_running() {
+        let limits = Limits { memory: Some(8 << 20), ..Limits::default() };
+        let source = "local ok, err = pcall(function() local t = {} for i = 1, 1e8 do t[i] = i end end) assert(not ok and err == 'not enough memory')";
+        assert_eq!(run(limits, source), None);
+        assert_eq!(run(limits, "local t = {} for i = 1, 1e8 do t[i] = i end"), Some(LimitExceeded::Memory(8 << 20)));
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use ansi_term::Color::{Green, Red, Yellow};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::fs_util::{open_file_if_exists, relative_path, write_file_atomically};
+use crate::ludorc::UserRc;
+use crate::native::{collect_transitive_natives, TransitiveNative};
+use crate::run::ScriptContext;
+
+pub const LOCK_FILE_NAME: &str = "ludo.lock";
+
+/// Records the native libraries a project expects to run, so that everyone working on it can
+/// check they're running the same binaries.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LockFile {
+    pub version: u32,
+    #[serde(default)]
+    pub natives: Vec<LockedNative>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LockedNative {
+    pub name: String,
+    /// Path to the binary, relative to the lock file.
+    pub path: PathBuf,
+    pub entry_point: String,
+    pub hash: String
+}
+
+impl LockedNative {
+    pub fn new(
+        transitive_native: &TransitiveNative,
+        lock_directory: &Path
+    ) -> Result<Self> {
+        let binary_path = transitive_native.bless.path.canonicalize()
+            .with_context(|| format!("Failed to canonicalise native binary path {}", transitive_native.bless.path.display()))?;
+        Ok(Self {
+            name: transitive_native.native.name.clone(),
+            path: relative_path(lock_directory, &binary_path),
+            entry_point: transitive_native.native.entry_point.to_string_lossy().into_owned(),
+            hash: transitive_native.bless.hash.clone()
+        })
+    }
+}
+
+impl LockFile {
+    pub fn new(
+        transitive_natives: &[TransitiveNative],
+        lock_directory: &Path
+    ) -> Result<Self> {
+        let mut natives = transitive_natives.iter()
+            .map(|x| LockedNative::new(x, lock_directory))
+            .collect::<Result<Vec<_>>>()?;
+        natives.sort_by(|a, b| a.path.cmp(&b.path));
+        natives.dedup();
+        Ok(Self { version: 1, natives })
+    }
+
+    pub fn load(
+        path: &Path
+    ) -> Result<Option<Self>> {
+        let Some(file) = open_file_if_exists(path)
+            .with_context(|| format!("Failed to load {LOCK_FILE_NAME} at {}", path.display()))?
+            else { return Ok(None) };
+        let lock: LockFile = serde_json::from_reader(file)
+            .with_context(|| format!("Failed to decode {LOCK_FILE_NAME} at {}", path.display()))?;
+        if lock.version != 1 {
+            bail!("Unsupported {LOCK_FILE_NAME} version: {}", lock.version);
+        }
+        Ok(Some(lock))
+    }
+
+    pub fn save(
+        &self,
+        path: &Path
+    ) -> Result<()> {
+        let mut contents = serde_json::to_vec_pretty(self).with_context(|| format!("Failed to encode {LOCK_FILE_NAME}"))?;
+        contents.push(b'\n');
+        write_file_atomically(path, &contents)
+            .with_context(|| format!("Failed to write {LOCK_FILE_NAME} at {}", path.display()))
+    }
+
+    /// Describes every way `actual` differs from this lock file. No differences means the lock
+    /// file is up to date.
+    pub fn differences(
+        &self,
+        actual: &LockFile
+    ) -> Vec<String> {
+        let expected: BTreeMap<_, _> = self.natives.iter().map(|x| (&x.path, x)).collect();
+        let found: BTreeMap<_, _> = actual.natives.iter().map(|x| (&x.path, x)).collect();
+        let mut differences = vec![];
+        for (path, expected) in &expected {
+            match found.get(path) {
+                None => differences.push(format!("{} ({}) is locked but no longer used", expected.name, path.display())),
+                Some(found) if found.hash != expected.hash =>
+                    differences.push(format!("{} ({}) has hash {}, but {} was locked", found.name, path.display(), found.hash, expected.hash)),
+                Some(found) if found.entry_point != expected.entry_point || found.name != expected.name =>
+                    differences.push(format!("{} ({}) is declared differently to when it was locked", found.name, path.display())),
+                Some(_) => ()
+            }
+        }
+        for (path, found) in &found {
+            if !expected.contains_key(path) {
+                differences.push(format!("{} ({}) is used but not locked", found.name, path.display()));
+            }
+        }
+        differences
+    }
+
+    pub fn contains(
+        &self,
+        locked: &LockedNative
+    ) -> bool {
+        self.natives.contains(locked)
+    }
+}
+
+pub fn lock_file_path(
+    script_location: &Path
+) -> Result<PathBuf> {
+    let workspace = script_location.parent().context("Ludo scripts must exist inside of a workspace")?;
+    Ok(workspace.join(LOCK_FILE_NAME))
+}
+
+pub fn lock_from_fs(
+    user_rc: Rc<UserRc>,
+    script_location: PathBuf,
+    check: bool
+) -> Result<()> {
+    let lock_path = lock_file_path(&script_location)?;
+    let lock_directory = lock_path.parent().context("Lock file must exist inside of a directory")?;
+    let context = ScriptContext::new_from_fs(user_rc, script_location).context("Failed to construct script context")?;
+    let transitive_natives = collect_transitive_natives(&context)?;
+    let actual = LockFile::new(&transitive_natives, lock_directory)?;
+
+    if check {
+        let Some(expected) = LockFile::load(&lock_path)?
+            else { bail!("No {LOCK_FILE_NAME} found at {}; run `ludo lock` to create one", lock_path.display()) };
+        let differences = expected.differences(&actual);
+        if differences.is_empty() {
+            println!("{}", Green.paint(format!("All native libraries match {}.", lock_path.display())));
+            return Ok(());
+        }
+        for difference in &differences {
+            println!("{} {difference}", Red.bold().paint("->"));
+        }
+        bail!("{} native {} out of date with {}", differences.len(), if differences.len() == 1 { "library is" } else { "libraries are" }, lock_path.display());
+    }
+
+    actual.save(&lock_path)?;
+    for locked in &actual.natives {
+        println!("-> {} {}", Yellow.bold().paint(&locked.name), locked.path.display());
+        println!("   hash: {}", locked.hash);
+    }
+    println!("{}", Green.paint(format!("Locked {} native {} in {}.",
+        actual.natives.len(),
+        if actual.natives.len() == 1 { "library" } else { "libraries" },
+        lock_path.display()
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked(
+        name: &str,
+        hash: &str
+    ) -> LockedNative {
+        LockedNative {
+            name: name.into(),
+            path: PathBuf::from(format!("{name}/lib{name}.so")),
+            entry_point: "luaopen_native".into(),
+            hash: hash.into()
+        }
+    }
+
+    #[test]
+    fn matching_lock_has_no_differences() {
+        let lock = LockFile { version: 1, natives: vec![locked("foo", "abc"), locked("bar", "def")] };
+        assert!(lock.differences(&lock.clone()).is_empty());
+    }
+
+    #[test]
+    fn drifted_hash_is_a_difference() {
+        let expected = LockFile { version: 1, natives: vec![locked("foo", "abc")] };
+        let actual = LockFile { version: 1, natives: vec![locked("foo", "xyz")] };
+        assert_eq!(expected.differences(&actual).len(), 1);
+    }
+
+    #[test]
+    fn added_and_removed_natives_are_differences() {
+        let expected = LockFile { version: 1, natives: vec![locked("foo", "abc")] };
+        let actual = LockFile { version: 1, natives: vec![locked("bar", "def")] };
+        assert_eq!(expected.differences(&actual).len(), 2);
+    }
+}
@@ -1,12 +1,43 @@
-use std::collections::HashMap;
-use std::ffi::OsString;
-use std::path::{Component, Path, PathBuf};
-use anyhow::{bail, Context, Result};
-use serde::Deserialize;
-use crate::fs_util::open_file_if_exists;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use crate::error::LudoError;
+use crate::fs_util::read_file_if_exists;
+use crate::jsonc::from_jsonc;
 
+/// Lint rule name which turns every lint on or off at once.
+const ALL_LINTS: &str = "*";
+
+/// How strictly Luau type checks a script.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LanguageMode {
+    NoCheck,
+    NonStrict,
+    Strict
+}
+
+/// A single .luaurc, as written. See https://github.com/luau-lang/luau/blob/master/rfcs/config-luaurc.md
 #[derive(Debug, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct LuauRc {
+    #[serde(default)]
+    pub language_mode: Option<LanguageMode>,
+    /// Lints to turn on or off by name, in the order they're written, where `*` stands for every
+    /// lint.
+    #[serde(default, deserialize_with = "deserialize_lint")]
+    pub lint: Vec<(String, bool)>,
+    #[serde(default)]
+    pub lint_errors: Option<bool>,
+    #[serde(default)]
+    pub type_errors: Option<bool>,
+    /// Names to treat as globals, on top of Luau's own.
+    #[serde(default)]
+    pub globals: Vec<String>,
     #[serde(default)]
     pub aliases: HashMap<String, PathBuf>
 }
@@ -16,7 +47,22 @@ impl LuauRc {
         mut self,
         workspace_root: &Path,
     ) -> Result<CanonicalLuauRc> {
+        // Luau applies rules in order, so `*` only overrides the rules written before it.
+        let mut lint = LintRules::default();
+        for (rule, enabled) in self.lint {
+            if rule == ALL_LINTS {
+                lint.all = Some(enabled);
+                lint.rules.clear();
+            } else {
+                lint.rules.insert(rule, enabled);
+            }
+        }
         Ok(CanonicalLuauRc {
+            language_mode: self.language_mode,
+            lint,
+            lint_errors: self.lint_errors,
+            type_errors: self.type_errors,
+            globals: self.globals,
             aliases: self.aliases.drain()
                 .map(|(alias, path)| Ok((alias, workspace_root.join(path).canonicalize()?)))
                 .collect::<Result<_>>()?
@@ -24,45 +70,211 @@ impl LuauRc {
     }
 }
 
+fn deserialize_lint<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Vec<(String, bool)>, D::Error> {
+    struct LintVisitor;
+
+    impl<'de> Visitor<'de> for LintVisitor {
+        type Value = Vec<(String, bool)>;
+
+        fn expecting(
+            &self,
+            formatter: &mut fmt::Formatter
+        ) -> fmt::Result {
+            formatter.write_str("a map of lint names to booleans")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(
+            self,
+            mut map: A
+        ) -> Result<Self::Value, A::Error> {
+            let mut lint = vec![];
+            while let Some(entry) = map.next_entry()? {
+                lint.push(entry);
+            }
+            Ok(lint)
+        }
+    }
+
+    deserializer.deserialize_map(LintVisitor)
+}
+
+/// Which lints are turned on. `all` applies to every lint not named in `rules`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LintRules {
+    pub all: Option<bool>,
+    pub rules: BTreeMap<String, bool>
+}
+
+/// The .luaurc files which apply to a script, composed from the outermost directory inwards.
+/// Settings left unset fall back to Luau's defaults: non-strict mode, lints reported as warnings,
+/// and type errors reported.
 #[derive(Debug, Default, Clone)]
 pub struct CanonicalLuauRc {
+    pub language_mode: Option<LanguageMode>,
+    pub lint: LintRules,
+    pub lint_errors: Option<bool>,
+    pub type_errors: Option<bool>,
+    pub globals: Vec<String>,
     pub aliases: HashMap<String, PathBuf>
 }
 
 impl CanonicalLuauRc {
+    /// Applies this configuration on top of one from an enclosing directory, as Luau does. Settings
+    /// made here win, lint rules are layered on top of the enclosing ones unless `*` resets them
+    /// all, and globals from both are declared.
     pub fn compose_atop(
         mut self,
         mut ancestor: Self
     ) -> Self {
+        ancestor.language_mode = self.language_mode.or(ancestor.language_mode);
+        ancestor.lint_errors = self.lint_errors.or(ancestor.lint_errors);
+        ancestor.type_errors = self.type_errors.or(ancestor.type_errors);
+        if self.lint.all.is_some() {
+            ancestor.lint = self.lint;
+        } else {
+            ancestor.lint.rules.append(&mut self.lint.rules);
+        }
+        for global in self.globals {
+            if !ancestor.globals.contains(&global) {
+                ancestor.globals.push(global);
+            }
+        }
         for (key, value) in self.aliases.drain() {
             ancestor.aliases.insert(key, value);
         }
         ancestor
     }
+
+    /// Every setting made here, named by its path in the .luaurc, like `aliases.lib`. Globals are
+    /// listed one by one, as each is declared separately.
+    pub fn settings(&self) -> Vec<(String, Value)> {
+        let mut settings = vec![];
+        if let Some(language_mode) = self.language_mode {
+            settings.push(("languageMode".into(), json!(language_mode)));
+        }
+        if let Some(all) = self.lint.all {
+            settings.push((format!("lint.{ALL_LINTS}"), json!(all)));
+        }
+        for (rule, enabled) in &self.lint.rules {
+            settings.push((format!("lint.{rule}"), json!(enabled)));
+        }
+        if let Some(lint_errors) = self.lint_errors {
+            settings.push(("lintErrors".into(), json!(lint_errors)));
+        }
+        if let Some(type_errors) = self.type_errors {
+            settings.push(("typeErrors".into(), json!(type_errors)));
+        }
+        for global in &self.globals {
+            settings.push((format!("globals.{global}"), json!(true)));
+        }
+        for (alias, path) in &self.aliases {
+            settings.push((format!("aliases.{alias}"), json!(path)));
+        }
+        settings
+    }
 }
 
-pub fn load_composite_luau_rc(
+/// Loads every .luaurc which applies to `path`, outermost first, alongside the directory each
+/// was found in.
+pub fn load_luau_rc_layers(
     path: &Path
-) -> Result<CanonicalLuauRc> {
-    path.ancestors()
-        .into_iter()
+) -> Result<Vec<(PathBuf, CanonicalLuauRc)>> {
+    let mut layers = path.ancestors()
         .map(|ancestor| -> Result<_> {
-            let Some(file) = open_file_if_exists(&ancestor.join(".luaurc"))
+            let Some(source) = read_file_if_exists(&ancestor.join(".luaurc"))
                 .with_context(|| format!("Failed to load .luaurc at {}", ancestor.display()))?
                 else { return Ok(None) };
-            Ok(Some((ancestor, file)))
+            Ok(Some((ancestor, source)))
         })
         .filter_map(Result::transpose)
         .map(|result| {
-            let (ancestor, file) = result?;
-            let rc: LuauRc = serde_json::from_reader(file)
-                .with_context(|| format!("Failed to decode .luaurc at {}", ancestor.display()))?;
+            let (ancestor, source) = result?;
+            let rc: LuauRc = from_jsonc(&source, &ancestor.join(".luaurc"))
+                .map_err(|err| LudoError::Config(format!("Failed to decode .luaurc at {}: {err}", ancestor.display())))?;
             let rc = rc.canonicalise(ancestor)
-                .with_context(|| format!("Failed to canonicalise .luaurc at {}", ancestor.display()))?;
-            Ok(rc)
+                .map_err(|err| LudoError::Config(format!("Failed to canonicalise .luaurc at {}: {err:#}", ancestor.display())))?;
+            Ok((ancestor.to_path_buf(), rc))
         })
-        .try_fold(
-            CanonicalLuauRc::default(),
-            |accum, item| item.map(|item| item.compose_atop(accum))
-        )
-}
\ No newline at end of file
+        .collect::<Result<Vec<_>>>()?;
+    layers.reverse();
+    Ok(layers)
+}
+
+pub fn load_composite_luau_rc(
+    path: &Path
+) -> Result<CanonicalLuauRc> {
+    Ok(load_luau_rc_layers(path)?
+        .into_iter()
+        .fold(CanonicalLuauRc::default(), |accum, (_, rc)| rc.compose_atop(accum)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+
+    fn parse(
+        json: &str
+    ) -> CanonicalLuauRc {
+        let rc: LuauRc = serde_json::from_str(json).expect(".luaurc should decode");
+        rc.canonicalise(Path::new(".")).expect(".luaurc should canonicalise")
+    }
+
+    #[test]
+    fn inner_settings_take_precedence() {
+        let outer = parse(r#"{
+            "languageMode": "strict",
+            "lintErrors": true,
+            "lint": { "*": true, "LocalUnused": false },
+            "globals": ["native", "shared"]
+        }"#);
+        let inner = parse(r#"{
+            "languageMode": "nocheck",
+            "typeErrors": false,
+            "lint": { "LocalShadow": false, "LocalUnused": true },
+            "globals": ["native", "extra"]
+        }"#);
+        let composed = inner.compose_atop(outer);
+        assert_eq!(composed.language_mode, Some(LanguageMode::NoCheck));
+        assert_eq!(composed.lint_errors, Some(true));
+        assert_eq!(composed.type_errors, Some(false));
+        assert_eq!(composed.lint.all, Some(true));
+        assert_eq!(composed.lint.rules, BTreeMap::from([("LocalShadow".into(), false), ("LocalUnused".into(), true)]));
+        assert_eq!(composed.globals, ["native", "shared", "extra"]);
+    }
+
+    #[test]
+    fn wildcard_lint_resets_enclosing_rules() {
+        let outer = parse(r#"{ "lint": { "LocalUnused": true } }"#);
+        let inner = parse(r#"{ "lint": { "*": false } }"#);
+        let composed = inner.compose_atop(outer);
+        assert_eq!(composed.lint, LintRules { all: Some(false), rules: BTreeMap::new() });
+    }
+
+    #[test]
+    fn wildcard_lint_only_overrides_earlier_rules() {
+        let before = parse(r#"{ "lint": { "LocalUnused": true, "*": false } }"#);
+        assert_eq!(before.lint, LintRules { all: Some(false), rules: BTreeMap::new() });
+        let after = parse(r#"{ "lint": { "*": false, "LocalUnused": true } }"#);
+        assert_eq!(after.lint, LintRules { all: Some(false), rules: BTreeMap::from([("LocalUnused".into(), true)]) });
+    }
+
+    #[test]
+    fn nested_luaurc_on_disk_takes_precedence() {
+        let dir = TempDir::with_files("luaurc", &[
+            (".luaurc", r#"{ "languageMode": "strict", "lintErrors": true, "globals": ["outer"] }"#),
+            ("inner/.luaurc", r#"{ "languageMode": "nocheck", "globals": ["inner"] }"#)
+        ]);
+        let composed = load_composite_luau_rc(&dir.join("inner")).expect(".luaurc files should load");
+        assert_eq!(composed.language_mode, Some(LanguageMode::NoCheck));
+        assert_eq!(composed.lint_errors, Some(true));
+        assert_eq!(composed.globals, ["outer", "inner"]);
+    }
+
+    #[test]
+    fn unknown_language_modes_are_rejected() {
+        assert!(serde_json::from_str::<LuauRc>(r#"{ "languageMode": "lenient" }"#).is_err());
+    }
+}
@@ -1,23 +1,53 @@
-use crate::fs_util::open_file_if_exists;
+use crate::error::LudoError;
+use crate::fs_util::{read_file_if_exists, write_file_atomically};
+use crate::jsonc::from_jsonc;
 use anyhow::{bail, Context, Result};
-use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
-use std::ffi::{CString};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CString;
+use std::fs;
+use std::fs::{File, OpenOptions};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::limits::Limits;
+use crate::ludorc_v1;
 use crate::native::BlessInfo;
+use crate::signing::find_signer;
 
-#[derive(Debug, Deserialize, Clone)]
+/// The version of the .ludorc format this version of Ludo writes. Older versions are upgraded
+/// when they're loaded, and rewritten by `ludo migrate`.
+pub const CURRENT_VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct UserRc {
     pub version: u32,
+    /// Blessed native hashes, alongside what was known about them when they were blessed.
     #[serde(default)]
-    pub blessed: HashSet<String>
+    pub blessed: BTreeMap<String, Blessing>,
+    /// Legacy blessings which couldn't be upgraded because their binary couldn't be verified.
+    /// These still apply to binaries matching the legacy hash.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub legacy_blessed: BTreeMap<String, Blessing>,
+    /// Publisher public keys. Any native binary with a valid signature from one of these keys
+    /// counts as blessed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trusted_keys: BTreeMap<String, TrustedKey>,
+    /// Fields this version of Ludo doesn't know about, kept so they survive a save.
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>
 }
 
 impl Default for UserRc {
     fn default() -> Self {
         Self {
-            version: 1,
-            blessed: HashSet::new()
+            version: CURRENT_VERSION,
+            blessed: BTreeMap::new(),
+            legacy_blessed: BTreeMap::new(),
+            trusted_keys: BTreeMap::new(),
+            unknown: Map::new()
         }
     }
 }
@@ -27,73 +57,668 @@ impl UserRc {
         &self,
         info: &BlessInfo
     ) -> bool {
-        self.blessed.contains(&info.hash)
+        self.blessed.contains_key(&info.hash)
+            || self.legacy_blessed.contains_key(&info.legacy_hash)
+            || info.signature.as_ref().is_some_and(|signature| find_signer(self, &info.hash, signature).is_some())
+    }
+
+    pub fn trust_key(
+        &mut self,
+        public_key: String,
+        name: Option<String>
+    ) {
+        let trusted_at = Some(humantime::format_rfc3339_seconds(SystemTime::now()).to_string());
+        self.trusted_keys.insert(public_key, TrustedKey { name, trusted_at });
+    }
+
+    pub fn bless(
+        &mut self,
+        info: &BlessInfo,
+        reason: Option<String>
+    ) {
+        self.legacy_blessed.remove(&info.legacy_hash);
+        self.blessed.insert(info.hash.clone(), Blessing::new(info, reason));
     }
+
+    /// Removes the blessings, legacy ones included, for which `revoke` returns true, and returns
+    /// them. `revoke` is also told whether each blessing is a legacy one, as is the caller.
+    pub fn unbless(
+        &mut self,
+        mut revoke: impl FnMut(&str, &Blessing, bool) -> bool
+    ) -> Vec<(String, Blessing, bool)> {
+        let mut revoked = vec![];
+        for (blessed, legacy) in [(&mut self.blessed, false), (&mut self.legacy_blessed, true)] {
+            blessed.retain(|hash, blessing| {
+                if !revoke(hash, blessing, legacy) {
+                    return true;
+                }
+                revoked.push((hash.clone(), blessing.clone(), legacy));
+                false
+            });
+        }
+        revoked
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Blessing {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blessed_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>
+}
+
+impl Blessing {
+    pub fn new(
+        info: &BlessInfo,
+        reason: Option<String>
+    ) -> Self {
+        Self {
+            title: Some(info.title.clone()),
+            path: Some(info.path.clone()),
+            blessed_at: Some(humantime::format_rfc3339_seconds(SystemTime::now()).to_string()),
+            reason
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedKey {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted_at: Option<String>
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct WorkspaceRc {
     pub version: u32,
-    #[serde(default)]
-    pub permissions: HashMap<String, Permissions>,
-    #[serde(default)]
-    pub native: Option<Native>
+    /// Stops this .ludorc inheriting from those in enclosing directories, like `root` in an
+    /// .editorconfig.
+    #[serde(default, rename = "root", skip_serializing_if = "std::ops::Not::not")]
+    pub is_root: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub permissions: BTreeMap<String, Permissions>,
+    /// The native library declared here. Unlike other settings, this is never inherited by the
+    /// .ludorc files of nested directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub native: Option<Native>,
+    /// Resource limits for scripts run from this workspace.
+    #[serde(default, skip_serializing_if = "Limits::is_empty")]
+    pub limits: Limits,
+    /// Fields this version of Ludo doesn't know about, like `$schema`, kept so they survive a
+    /// migration.
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>,
+    /// The directory containing this .ludorc, if it was loaded from a file.
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+    /// Grants made by the .ludorc files of enclosing directories, nearest first.
+    #[serde(skip)]
+    pub inherited: Vec<Grant>
 }
 
 impl Default for WorkspaceRc {
     fn default() -> Self {
         Self {
-            version: 1,
-            permissions: HashMap::new(),
-            native: None
+            version: CURRENT_VERSION,
+            is_root: false,
+            permissions: BTreeMap::new(),
+            native: None,
+            limits: Limits::default(),
+            unknown: Map::new(),
+            root: None,
+            inherited: vec![]
+        }
+    }
+}
+
+impl WorkspaceRc {
+    /// Applies the permissions and limits of an enclosing directory's .ludorc beneath this one.
+    /// Settings made here win, and permissions granted by either are granted. The ancestor's
+    /// grants are kept apart from those made here, as their aliases mean what they do there.
+    fn inherit(
+        mut self,
+        ancestor: WorkspaceRc
+    ) -> Self {
+        self.inherited.extend(ancestor.grants());
+        self.limits = self.limits.or(ancestor.limits);
+        self
+    }
+
+    /// Every grant which applies here, those made by this .ludorc first, then those it inherits.
+    pub fn grants(&self) -> Vec<Grant> {
+        let own = self.root.iter().flat_map(|root| self.permissions.iter().map(|(alias, permissions)| Grant {
+            alias: alias.clone(),
+            granted_in: root.clone(),
+            permissions: permissions.clone()
+        }));
+        own.chain(self.inherited.iter().cloned()).collect()
+    }
+
+    /// Every setting made here, named by its path in the .ludorc, like `limits.timeout`. Granted
+    /// directories are listed one by one, as each is granted separately.
+    pub fn settings(&self) -> Vec<(String, Value)> {
+        let mut settings = vec![("version".to_string(), json!(self.version))];
+        if self.is_root {
+            settings.push(("root".into(), json!(true)));
+        }
+        if let Some(native) = &self.native {
+            settings.push(("native.name".into(), json!(native.name)));
+            settings.push(("native.parent".into(), json!(native.parent)));
+            settings.push(("native.entryPoint".into(), json!(native.entry_point.to_string_lossy())));
+            if let Some(signature) = &native.signature {
+                settings.push(("native.signature".into(), json!(signature)));
+            }
+        }
+        settings.extend(self.limits.settings());
+        let mut granted = BTreeSet::new();
+        for Grant { alias, permissions, .. } in self.grants() {
+            if permissions.native {
+                granted.insert(format!("permissions.{alias}.native"));
+            }
+            if permissions.process {
+                granted.insert(format!("permissions.{alias}.process"));
+            }
+            for directory in &permissions.fs {
+                granted.insert(format!("permissions.{alias}.fs.{}", directory.display()));
+            }
         }
+        settings.extend(granted.into_iter().map(|key| (key, json!(true))));
+        settings
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct Permissions {
-    pub native: bool
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub native: bool,
+    /// Allows requiring `@ludo/process`, to read the environment and spawn subprocesses.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub process: bool,
+    /// Directories which may be used through `@ludo/fs`, relative to the .ludorc granting them.
+    /// These are made absolute once loaded, so they survive being inherited.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fs: Vec<PathBuf>
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Permissions which a .ludorc grants to the package it names as `alias`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub alias: String,
+    /// The directory of the .ludorc making the grant. `alias` is resolved from here, with the
+    /// .luaurc files which apply here, even when the grant is inherited.
+    pub granted_in: PathBuf,
+    pub permissions: Permissions
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Native {
     pub name: String,
     pub parent: PathBuf,
-    pub entry_point: CString
+    #[serde(serialize_with = "serialize_c_string")]
+    pub entry_point: CString,
+    /// Base64 ed25519 signature over the SHA3-256 digest of the binary, made with `ludo key sign`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>
+}
+
+fn serialize_c_string<S: Serializer>(
+    string: &CString,
+    serializer: S
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&string.to_string_lossy())
+}
+
+/// Just enough of any .ludorc to tell which version of the format it's written in.
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32
+}
+
+fn decode<T: DeserializeOwned>(
+    source: &str,
+    path: &Path
+) -> Result<T> {
+    Ok(from_jsonc(source, path)
+        .map_err(|err| LudoError::Config(format!("Failed to decode {}: {err}", path.display())))?)
+}
+
+/// Decodes a .ludorc of any supported version, returning the version it was written in.
+fn decode_versioned<T: DeserializeOwned, V1: DeserializeOwned>(
+    source: &str,
+    path: &Path,
+    upgrade: impl FnOnce(V1) -> T
+) -> Result<(u32, T)> {
+    let Versioned { version } = decode(source, path)?;
+    match version {
+        1 => Ok((version, upgrade(decode(source, path)?))),
+        CURRENT_VERSION => Ok((version, decode(source, path)?)),
+        version => bail!(LudoError::Config(format!("Unsupported ludorc version {version} in {}, expected at most version {CURRENT_VERSION}", path.display())))
+    }
+}
+
+/// Decodes a user .ludorc, upgrading it to the current version, which is returned alongside the
+/// version it was written in.
+pub fn decode_user_rc(
+    source: &str,
+    path: &Path
+) -> Result<(u32, UserRc)> {
+    decode_versioned(source, path, ludorc_v1::UserRc::upgrade)
+}
+
+/// Decodes a workspace .ludorc, upgrading it to the current version, which is returned alongside
+/// the version it was written in. Granted directories are left relative to the file.
+pub fn decode_workspace_rc(
+    source: &str,
+    path: &Path
+) -> Result<(u32, WorkspaceRc)> {
+    decode_versioned(source, path, ludorc_v1::WorkspaceRc::upgrade)
+}
+
+pub fn user_rc_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home_dir| home_dir.join(".ludorc"))
+}
+
+/// Reads and writes a user .ludorc, holding a lock file so that concurrent Ludo
+/// processes don't lose each other's changes.
+pub struct UserRcStore {
+    path: PathBuf
+}
+
+impl UserRcStore {
+    pub fn new(
+        path: PathBuf
+    ) -> Self {
+        Self { path }
+    }
+
+    pub fn at_home() -> Result<Self> {
+        let path = user_rc_path().context("No home directory found to store the user .ludorc in")?;
+        Ok(Self::new(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lock(
+        &self,
+        exclusive: bool
+    ) -> Result<File> {
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let lock_file = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)
+            .with_context(|| format!("Failed to open lock file at {}", lock_path.display()))?;
+        let locked = if exclusive { lock_file.lock() } else { lock_file.lock_shared() };
+        locked.with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+        Ok(lock_file)
+    }
+
+    fn read(&self) -> Result<Option<UserRc>> {
+        let Some(source) = read_file_if_exists(&self.path)? else { return Ok(None) };
+        let (_, rc) = decode_user_rc(&source, &self.path)?;
+        Ok(Some(rc))
+    }
+
+    fn write(
+        &self,
+        rc: &UserRc
+    ) -> Result<()> {
+        let contents = encode(rc).context("Failed to encode user .ludorc")?;
+        write_file_atomically(&self.path, &contents)
+            .with_context(|| format!("Failed to write user .ludorc at {}", self.path.display()))
+    }
+
+    /// Reads the file, or returns `None` if it hasn't been created yet.
+    pub fn load(&self) -> Result<Option<UserRc>> {
+        if !self.path.exists() { return Ok(None) }
+        let _lock = self.lock(false)?;
+        self.read()
+    }
+
+    /// Rewrites the file in the current version of the format, returning the version it was
+    /// written in, or `None` if it hasn't been created yet. `approve` is given the file's source
+    /// and what it would be rewritten as, and decides whether it is.
+    pub fn migrate(
+        &self,
+        approve: impl FnOnce(&str, &[u8]) -> bool
+    ) -> Result<Option<u32>> {
+        if !self.path.exists() { return Ok(None) }
+        let _lock = self.lock(true)?;
+        let Some(source) = read_file_if_exists(&self.path)? else { return Ok(None) };
+        let (version, rc) = decode_user_rc(&source, &self.path)?;
+        if version != CURRENT_VERSION {
+            let contents = encode(&rc).context("Failed to encode user .ludorc")?;
+            if approve(&source, &contents) {
+                write_file_atomically(&self.path, &contents)
+                    .with_context(|| format!("Failed to write user .ludorc at {}", self.path.display()))?;
+            }
+        }
+        Ok(Some(version))
+    }
+
+    /// Reads the file, applies `change` and writes the result back, without letting
+    /// any other process write in between. Creates the file if it doesn't exist.
+    pub fn modify<T>(
+        &self,
+        change: impl FnOnce(&mut UserRc) -> Result<T>
+    ) -> Result<T> {
+        let _lock = self.lock(true)?;
+        let mut rc = self.read()?.unwrap_or_default();
+        let output = change(&mut rc)?;
+        self.write(&rc)?;
+        Ok(output)
+    }
 }
 
 pub fn load_user_rc() -> Result<Option<UserRc>> {
-    let Some(home_dir) = dirs::home_dir() else { return Ok(None) };
-    let rc_path = home_dir.join(".ludorc");
-    let Some(file) = open_file_if_exists(&rc_path)? else { return Ok(None) };
-    let rc: UserRc = serde_json::from_reader(file)?;
-    if rc.version != 1 {
-        bail!("Unsupported ludorc version: {}", rc.version);
+    let Some(rc_path) = user_rc_path() else { return Ok(None) };
+    UserRcStore::new(rc_path).load()
+}
+
+/// Encodes a .ludorc as it's written to disk.
+fn encode(
+    rc: &impl Serialize
+) -> Result<Vec<u8>> {
+    let mut contents = serde_json::to_vec_pretty(rc)?;
+    contents.push(b'\n');
+    Ok(contents)
+}
+
+/// Rewrites the workspace .ludorc at `path` in the current version of the format, returning the
+/// version it was written in. `approve` is given the file's source and what it would be
+/// rewritten as, and decides whether it is.
+pub fn migrate_workspace_rc(
+    path: &Path,
+    approve: impl FnOnce(&str, &[u8]) -> bool
+) -> Result<u32> {
+    let source = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (version, rc) = decode_workspace_rc(&source, path)?;
+    if version != CURRENT_VERSION {
+        let contents = encode(&rc).context("Failed to encode .ludorc")?;
+        if approve(&source, &contents) {
+            write_file_atomically(path, &contents).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+    }
+    Ok(version)
+}
+
+/// Loads the .ludorc files which apply to `path`, nearest first, stopping at the first which is
+/// marked as the root or at the filesystem root.
+pub fn load_workspace_rc_layers(
+    path: &Path
+) -> Result<Vec<WorkspaceRc>> {
+    let mut layers = vec![];
+    for ancestor in path.ancestors() {
+        let rc_path = ancestor.join(".ludorc");
+        let Some(source) = read_file_if_exists(&rc_path)
+            .with_context(|| format!("Failed to load .ludorc at {}", ancestor.display()))?
+        else { continue };
+        let (_, mut rc) = decode_workspace_rc(&source, &rc_path)?;
+        for permissions in rc.permissions.values_mut() {
+            permissions.fs = permissions.fs.iter().map(|directory| ancestor.join(directory)).collect();
+        }
+        rc.root = Some(ancestor.to_path_buf());
+        let is_root = rc.is_root;
+        layers.push(rc);
+        if is_root { break }
     }
-    Ok(Some(rc))
+    Ok(layers)
 }
 
+/// Loads the .ludorc which governs `path`, composed with those of enclosing directories. The
+/// nearest .ludorc decides the workspace root and native library.
 pub fn load_workspace_rc(
     path: &Path
 ) -> Result<WorkspaceRc> {
-    path.ancestors()
-        .into_iter()
-        .map(|ancestor| -> Result<_> {
-            let Some(file) = open_file_if_exists(&ancestor.join(".ludorc"))
-                .with_context(|| format!("Failed to load .ludorc at {}", ancestor.display()))?
-            else { return Ok(None) };
-            Ok(Some((ancestor, file)))
-        })
-        .filter_map(Result::transpose)
-        .map(|result| {
-            let (ancestor, file) = result?;
-            let rc: WorkspaceRc = serde_json::from_reader(file)
-                .with_context(|| format!("Failed to decode .ludorc at {}", ancestor.display()))?;
-            if rc.version != 1 {
-                bail!("Unsupported ludorc version: {}", rc.version);
-            }
-            Ok(rc)
-        })
-        .next()
-        .unwrap_or(Ok(WorkspaceRc::default()))
-}
\ No newline at end of file
+    let mut layers = load_workspace_rc_layers(path)?.into_iter();
+    let Some(nearest) = layers.next() else { return Ok(WorkspaceRc::default()) };
+    Ok(layers.fold(nearest, WorkspaceRc::inherit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+
+    fn decode_user(
+        source: &str
+    ) -> (u32, UserRc) {
+        decode_user_rc(source, Path::new(".ludorc")).expect("User .ludorc should decode")
+    }
+
+    #[test]
+    fn user_rc_keeps_unknown_fields() {
+        let (version, rc) = decode_user(r#"{ "version": 1, "blessed": ["abc"], "editor": { "theme": "dark" } }"#);
+        assert_eq!(version, 1);
+        assert!(rc.legacy_blessed.contains_key("abc"), "Unverifiable legacy hashes should be kept aside");
+        let encoded = serde_json::to_value(&rc).expect("User .ludorc should encode");
+        assert_eq!(encoded["editor"]["theme"], "dark", "Unknown fields should survive a round trip");
+        assert_eq!(encoded["version"], CURRENT_VERSION);
+        let (version, decoded) = decode_user(&encoded.to_string());
+        assert_eq!(version, CURRENT_VERSION);
+        assert_eq!(decoded.legacy_blessed, rc.legacy_blessed);
+    }
+
+    #[test]
+    fn unbless_reaches_legacy_blessings() {
+        let (_, mut rc) = decode_user(r#"{
+            "version": 2,
+            "blessed": { "current": { "path": "/lib/libfoo.so" }, "bare": {} },
+            "legacyBlessed": { "old": { "path": "/lib/libold.so" } }
+        }"#);
+        let revoked = rc.unbless(|_, blessing, legacy| legacy || blessing.path.is_none());
+        let revoked: Vec<_> = revoked.iter().map(|(hash, _, legacy)| (hash.as_str(), *legacy)).collect();
+        assert_eq!(revoked, [("bare", false), ("old", true)]);
+        assert!(rc.blessed.contains_key("current"));
+        assert!(rc.legacy_blessed.is_empty());
+    }
+
+    #[test]
+    fn user_rc_reads_blessing_metadata() {
+        let (_, v1) = decode_user(r#"{
+            "version": 1,
+            "hash_format": 2,
+            "blessed": { "abc": { "title": "foo", "path": "/lib/libfoo.so", "reason": "testing", "blessed_at": "2025-01-01T00:00:00Z" } }
+        }"#);
+        let (_, v2) = decode_user(r#"{
+            "version": 2,
+            "blessed": { "abc": { "title": "foo", "path": "/lib/libfoo.so", "reason": "testing", "blessedAt": "2025-01-01T00:00:00Z" } }
+        }"#);
+        for rc in [v1, v2] {
+            let blessing = &rc.blessed["abc"];
+            assert_eq!(blessing.title.as_deref(), Some("foo"));
+            assert_eq!(blessing.path.as_deref(), Some(Path::new("/lib/libfoo.so")));
+            assert_eq!(blessing.reason.as_deref(), Some("testing"));
+            assert_eq!(blessing.blessed_at.as_deref(), Some("2025-01-01T00:00:00Z"));
+        }
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let err = decode_user_rc(r#"{ "version": 3 }"#, Path::new(".ludorc")).unwrap_err();
+        assert!(err.to_string().contains("Unsupported ludorc version 3"), "{err}");
+    }
+
+    fn temp_workspace(
+        name: &str,
+        ludorcs: &[(&str, &str)]
+    ) -> TempDir {
+        let files: Vec<_> = ludorcs.iter().map(|(directory, contents)| (Path::new(directory).join(".ludorc"), *contents)).collect();
+        TempDir::with_files(name, &files)
+    }
+
+    #[test]
+    fn nested_ludorc_inherits_permissions_and_limits() {
+        let dir = temp_workspace("inherit", &[
+            ("", r#"{
+                "version": 1,
+                "native": { "name": "outer", "parent": ".", "entry_point": "open" },
+                "permissions": { "@lib": { "native": true, "fs": ["./cache"] }, "@tool": { "process": true } },
+                "limits": { "timeout": "10s", "instructions": 1000 }
+            }"#),
+            ("pkg", r#"{
+                "version": 1,
+                "permissions": { "@lib": { "fs": ["./data", "../cache"] } },
+                "limits": { "timeout": "1s" }
+            }"#)
+        ]);
+        let rc = load_workspace_rc(&dir.join("pkg")).unwrap();
+        assert_eq!(rc.root.as_deref(), Some(dir.join("pkg").as_path()), "The nearest .ludorc should be the workspace root");
+        assert!(rc.native.is_none(), "Native libraries shouldn't be inherited");
+        let grants: Vec<_> = rc.grants().into_iter().map(|x| (x.alias, x.granted_in, x.permissions)).collect();
+        assert_eq!(grants, [
+            ("@lib".into(), dir.join("pkg"), Permissions { fs: vec![dir.join("pkg/./data"), dir.join("pkg/../cache")], ..Permissions::default() }),
+            ("@lib".into(), dir.to_path_buf(), Permissions { native: true, fs: vec![dir.join("./cache")], ..Permissions::default() }),
+            ("@tool".into(), dir.to_path_buf(), Permissions { process: true, ..Permissions::default() })
+        ], "Inherited grants should be kept with the directory which made them");
+        assert_eq!(rc.limits.timeout, Some(std::time::Duration::from_secs(1)));
+        assert_eq!(rc.limits.instructions, Some(1000));
+
+        let outer = load_workspace_rc(&dir).unwrap();
+        assert_eq!(outer.native.map(|x| x.name), Some("outer".into()), "A .ludorc's own native library should apply");
+    }
+
+    #[test]
+    fn root_ludorc_stops_inheritance() {
+        let dir = temp_workspace("root", &[
+            ("", r#"{ "version": 1, "permissions": { "@lib": { "native": true } }, "limits": { "memory": 1024 } }"#),
+            ("pkg", r#"{ "version": 1, "root": true }"#),
+            ("pkg/nested", r#"{ "version": 1 }"#)
+        ]);
+        let rc = load_workspace_rc(&dir.join("pkg/nested")).unwrap();
+        assert!(rc.grants().is_empty());
+        assert_eq!(rc.limits, Limits::default());
+        assert_eq!(load_workspace_rc_layers(&dir.join("pkg/nested")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn every_inherited_ludorc_must_be_supported() {
+        let dir = temp_workspace("version", &[
+            ("", r#"{ "version": 3 }"#),
+            ("pkg", r#"{ "version": 2 }"#)
+        ]);
+        let err = load_workspace_rc(&dir.join("pkg")).unwrap_err();
+        assert!(format!("{err:#}").contains("Unsupported ludorc version 3"), "{err:#}");
+    }
+
+    #[test]
+    fn workspace_rc_is_migrated_in_place() {
+        let dir = temp_workspace("migrate", &[("", r#"{
+            // Comments are allowed, but aren't kept
+            "version": 1,
+            "native": { "name": "foo", "parent": "./lib", "entry_point": "luaopen_foo" },
+            "permissions": { "@lib": { "fs": ["./cache"] } },
+            "limits": { "timeout": "90s" },
+        }"#)]);
+        let path = dir.join(".ludorc");
+        let original = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(migrate_workspace_rc(&path, |_, _| false).unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), original, "Unapproved migrations shouldn't write");
+        assert_eq!(migrate_workspace_rc(&path, |_, _| true).unwrap(), 1);
+        let migrated: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(migrated, json!({
+            "version": CURRENT_VERSION,
+            "native": { "name": "foo", "parent": "./lib", "entryPoint": "luaopen_foo" },
+            "permissions": { "@lib": { "fs": ["./cache"] } },
+            "limits": { "timeout": "1m 30s" }
+        }));
+        let unchanged = migrate_workspace_rc(&path, |_, _| panic!("Current files shouldn't be rewritten")).unwrap();
+        assert_eq!(unchanged, CURRENT_VERSION, "Migrating again should change nothing");
+    }
+
+    #[test]
+    fn schema_describes_every_workspace_rc_field() {
+        let schema: Value = serde_json::from_str(include_str!("../schema/ludorc.schema.json")).expect("Schema should be valid JSON");
+        assert_eq!(schema["properties"]["version"]["const"], CURRENT_VERSION);
+        let (_, rc) = decode_workspace_rc(r#"{
+            "version": 2,
+            "root": true,
+            "native": { "name": "foo", "parent": ".", "entryPoint": "luaopen_foo", "signature": "abc" },
+            "permissions": { "@lib": { "native": true, "process": true, "fs": ["./cache"] } },
+            "limits": { "memory": 1024, "timeout": "1s", "instructions": 1000 }
+        }"#, Path::new(".ludorc")).unwrap();
+        let encoded = serde_json::to_value(&rc).unwrap();
+        let described = |value: &Value, properties: &Value| value.as_object().unwrap().keys()
+            .all(|key| properties.get(key).is_some());
+        let properties = &schema["properties"];
+        assert!(described(&encoded, properties));
+        assert!(described(&encoded["native"], &properties["native"]["properties"]));
+        assert!(described(&encoded["limits"], &properties["limits"]["properties"]));
+        assert!(described(&encoded["permissions"]["@lib"], &properties["permissions"]["additionalProperties"]["properties"]));
+    }
+
+    #[test]
+    fn schema_describes_every_user_rc_field() {
+        let schema: Value = serde_json::from_str(include_str!("../schema/user-ludorc.schema.json")).expect("Schema should be valid JSON");
+        assert_eq!(schema["properties"]["version"]["const"], CURRENT_VERSION);
+        let (_, rc) = decode_user(r#"{
+            "version": 2,
+            "blessed": { "abc": { "title": "foo", "path": "/lib/libfoo.so", "blessedAt": "2025-01-01T00:00:00Z", "reason": "testing" } },
+            "legacyBlessed": { "def": { "title": "old" } },
+            "trustedKeys": { "key": { "name": "Publisher", "trustedAt": "2025-01-01T00:00:00Z" } }
+        }"#);
+        let encoded = serde_json::to_value(&rc).unwrap();
+        let described = |value: &Value, properties: &Value| value.as_object().unwrap().keys()
+            .all(|key| properties.get(key).is_some());
+        let properties = &schema["properties"];
+        assert!(described(&encoded, properties));
+        assert!(described(&encoded["blessed"]["abc"], &schema["$defs"]["blessing"]["properties"]));
+        assert!(described(&encoded["trustedKeys"]["key"], &properties["trustedKeys"]["additionalProperties"]["properties"]));
+    }
+
+    fn temp_store(
+        name: &str
+    ) -> (TempDir, UserRcStore) {
+        let dir = TempDir::new(name);
+        let store = UserRcStore::new(dir.join(".ludorc"));
+        (dir, store)
+    }
+
+    #[test]
+    fn store_creates_file_on_first_modify() {
+        let (_dir, store) = temp_store("create");
+        assert!(store.load().expect("Missing file should load").is_none());
+        store.modify(|rc| { rc.blessed.insert("abc".into(), Blessing::default()); Ok(()) }).expect("Modify should succeed");
+        let rc = store.load().expect("File should load").expect("File should exist");
+        assert_eq!(rc.version, CURRENT_VERSION);
+        assert!(rc.blessed.contains_key("abc"));
+    }
+
+    #[test]
+    fn store_modify_keeps_unrelated_keys() {
+        let (_dir, store) = temp_store("unrelated");
+        std::fs::write(store.path(), r#"{ "version": 1, "hash_format": 2, "blessed": ["old"], "other": [1, 2] }"#).unwrap();
+        store.modify(|rc| { rc.blessed.insert("new".into(), Blessing::default()); Ok(()) }).expect("Modify should succeed");
+        let encoded: Value = serde_json::from_slice(&std::fs::read(store.path()).unwrap()).unwrap();
+        assert_eq!(encoded["other"], serde_json::json!([1, 2]));
+        assert_eq!(encoded["blessed"], serde_json::json!({ "new": {}, "old": {} }));
+    }
+
+    #[test]
+    fn store_concurrent_modifies_are_not_lost() {
+        let (_dir, store) = temp_store("concurrent");
+        let path = store.path().to_path_buf();
+        let threads: Vec<_> = (0..8).map(|i| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                UserRcStore::new(path).modify(|rc| { rc.blessed.insert(format!("hash{i}"), Blessing::default()); Ok(()) })
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap().expect("Modify should succeed");
+        }
+        let rc = store.load().unwrap().unwrap();
+        assert_eq!(rc.blessed.len(), 8, "Every concurrent blessing should be kept");
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use crate::limits::Limits;
+use crate::ludorc::{self, Permissions, CURRENT_VERSION};
+use crate::native::{content_hash, legacy_hash};
+
+/// Hashes cover only the contents of the native binary.
+const CONTENT_HASH_FORMAT: u32 = 2;
+/// Hashes covered the title and absolute path of the native binary, alongside its contents.
+const LEGACY_HASH_FORMAT: u32 = 1;
+
+fn legacy_hash_format() -> u32 {
+    LEGACY_HASH_FORMAT
+}
+
+/// A user .ludorc in the first version of the format, which used snake_case keys.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UserRc {
+    /// Always 1. Read only so it isn't kept among the unknown fields.
+    #[serde(rename = "version")]
+    _version: u32,
+    /// The format of the hashes in `blessed`. Files without this field predate content-addressed
+    /// hashes.
+    #[serde(default = "legacy_hash_format")]
+    pub hash_format: u32,
+    #[serde(default, deserialize_with = "deserialize_blessed")]
+    pub blessed: BTreeMap<String, Blessing>,
+    #[serde(default)]
+    pub legacy_blessed: BTreeMap<String, Blessing>,
+    #[serde(default)]
+    pub trusted_keys: BTreeMap<String, TrustedKey>,
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Blessing {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub blessed_at: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TrustedKey {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub trusted_at: Option<String>
+}
+
+/// Older user .ludorc files list blessed hashes without any metadata.
+fn deserialize_blessed<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<BTreeMap<String, Blessing>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Blessed {
+        Hashes(Vec<String>),
+        Entries(BTreeMap<String, Blessing>)
+    }
+    Ok(match Blessed::deserialize(deserializer)? {
+        Blessed::Hashes(hashes) => hashes.into_iter().map(|hash| (hash, Blessing::default())).collect(),
+        Blessed::Entries(entries) => entries
+    })
+}
+
+impl From<Blessing> for ludorc::Blessing {
+    fn from(
+        blessing: Blessing
+    ) -> Self {
+        Self { title: blessing.title, path: blessing.path, blessed_at: blessing.blessed_at, reason: blessing.reason }
+    }
+}
+
+impl UserRc {
+    /// Converts this to the current format. Blessings made with legacy hashes are re-hashed if
+    /// their binary still exists and still matches; the rest are kept aside as legacy blessings.
+    pub fn upgrade(self) -> ludorc::UserRc {
+        let mut blessed = BTreeMap::new();
+        let mut legacy_blessed: BTreeMap<_, ludorc::Blessing> = self.legacy_blessed.into_iter()
+            .map(|(hash, blessing)| (hash, blessing.into()))
+            .collect();
+        for (hash, blessing) in self.blessed {
+            if self.hash_format >= CONTENT_HASH_FORMAT {
+                blessed.insert(hash, blessing.into());
+                continue;
+            }
+            let verified_bytes = match (&blessing.title, &blessing.path) {
+                (Some(title), Some(path)) => fs::read(path).ok()
+                    .filter(|bytes| legacy_hash(title, path, bytes) == hash),
+                _ => None
+            };
+            match verified_bytes {
+                Some(bytes) => { blessed.insert(content_hash(&bytes), blessing.into()); },
+                None => { legacy_blessed.insert(hash, blessing.into()); }
+            }
+        }
+        ludorc::UserRc {
+            version: CURRENT_VERSION,
+            blessed,
+            legacy_blessed,
+            trusted_keys: self.trusted_keys.into_iter()
+                .map(|(key, trusted)| (key, ludorc::TrustedKey { name: trusted.name, trusted_at: trusted.trusted_at }))
+                .collect(),
+            unknown: self.unknown
+        }
+    }
+}
+
+/// A workspace .ludorc in the first version of the format, which used snake_case keys.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WorkspaceRc {
+    /// Always 1. Read only so it isn't kept among the unknown fields.
+    #[serde(rename = "version")]
+    _version: u32,
+    #[serde(default, rename = "root")]
+    pub is_root: bool,
+    #[serde(default)]
+    pub permissions: HashMap<String, Permissions>,
+    #[serde(default)]
+    pub native: Option<Native>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Native {
+    pub name: String,
+    pub parent: PathBuf,
+    pub entry_point: CString,
+    #[serde(default)]
+    pub signature: Option<String>
+}
+
+impl WorkspaceRc {
+    /// Converts this to the current format.
+    pub fn upgrade(self) -> ludorc::WorkspaceRc {
+        ludorc::WorkspaceRc {
+            version: CURRENT_VERSION,
+            is_root: self.is_root,
+            permissions: self.permissions.into_iter().collect(),
+            native: self.native.map(|native| ludorc::Native {
+                name: native.name,
+                parent: native.parent,
+                entry_point: native.entry_point,
+                signature: native.signature
+            }),
+            limits: self.limits,
+            unknown: self.unknown,
+            root: None,
+            inherited: vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+    use crate::native::BlessInfo;
+
+    #[test]
+    fn legacy_blessings_are_upgraded() {
+        let dir = TempDir::with_files("upgrade", &[("libfoo.so", "binary")]);
+        let binary_path = dir.join("libfoo.so");
+        let verified = legacy_hash("foo", &binary_path, b"binary");
+        let rc = UserRc {
+            _version: 1,
+            hash_format: LEGACY_HASH_FORMAT,
+            blessed: [
+                (verified, Blessing { title: Some("foo".into()), path: Some(binary_path.clone()), ..Blessing::default() }),
+                ("unverifiable".into(), Blessing::default())
+            ].into_iter().collect(),
+            legacy_blessed: BTreeMap::new(),
+            trusted_keys: BTreeMap::new(),
+            unknown: Map::new()
+        };
+        let rc = rc.upgrade();
+        assert_eq!(rc.version, CURRENT_VERSION);
+        assert!(rc.blessed.contains_key(&content_hash(b"binary")), "Verified blessings should be re-hashed");
+        assert!(rc.legacy_blessed.contains_key("unverifiable"), "Unverifiable blessings should be kept aside");
+        let info = BlessInfo::new("foo".into(), binary_path, b"binary");
+        assert!(rc.is_blessed(&info));
+    }
+
+    #[test]
+    fn workspace_rc_keys_are_renamed() {
+        let rc: WorkspaceRc = serde_json::from_str(r#"{
+            "version": 1,
+            "$schema": "https://example.com/ludorc.schema.json",
+            "native": { "name": "foo", "parent": ".", "entry_point": "luaopen_foo" },
+            "permissions": { "@lib": { "native": true } }
+        }"#).unwrap();
+        let encoded = serde_json::to_value(rc.upgrade()).unwrap();
+        assert_eq!(encoded, serde_json::json!({
+            "version": CURRENT_VERSION,
+            "$schema": "https://example.com/ludorc.schema.json",
+            "native": { "name": "foo", "parent": ".", "entryPoint": "luaopen_foo" },
+            "permissions": { "@lib": { "native": true } }
+        }));
+    }
+}
@@ -1,39 +1,140 @@
 mod cli;
+mod bless;
 mod ludorc;
+mod ludorc_v1;
 mod run;
 mod native;
+mod require;
 mod luaurc;
 mod fs_util;
+mod lock;
+mod trust;
+mod signing;
+mod repl;
+mod permissions;
+mod process;
+mod filesystem;
+mod task;
+mod limits;
+mod diagnostics;
+mod error;
+mod jsonc;
+mod config;
+mod migrate;
 
 use std::env;
-use crate::cli::Args;
+use std::io::{stdin, stdout, IsTerminal};
+use crate::cli::{Args, Commands, KeyCommands};
 use crate::ludorc::load_user_rc;
 use anyhow::{Context, Result};
 use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::rc::Rc;
-use ansi_term::Color::Green;
-use ansi_term::Style;
+use crate::config::print_config;
+use crate::error::report_error;
+use crate::bless::{bless_from_fs, list_blessings, print_hash_from_fs, unbless};
+use crate::lock::lock_from_fs;
+use crate::migrate::migrate;
+use crate::signing::{generate_key, list_trusted_keys, sign_binary, trust_key, untrust_key, verify_binary};
+use crate::repl::run_repl;
+use crate::run::{run_from_fs, RunOptions, ScriptExit};
+use crate::trust::AllowList;
+#[cfg(windows)]
 use log::warn;
-use crate::run::run_from_fs;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+	#[cfg(windows)]
 	if ansi_term::enable_ansi_support().is_err() {
 		warn!("Could not enable ANSI support");
 	}
 	let args = Args::parse();
-	if args.no_args_passed() {
+	let error_format = args.error_format;
+	match run_command(args) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(err) => {
+			if let Some(ScriptExit(status)) = err.downcast_ref() {
+				return ExitCode::from(*status);
+			}
+			ExitCode::from(report_error(&err, error_format))
+		}
+	}
+}
+
+fn run_command(
+	args: Args
+) -> Result<()> {
+	let interactive = stdin().is_terminal() && stdout().is_terminal();
+	if args.no_args_passed() && !interactive {
 		let crate_version = env!("CARGO_PKG_VERSION");
 		println!("ludo {crate_version}");
 		return Ok(());
 	}
 	let user_rc = Rc::new(load_user_rc().context("Failed to load user .ludorc")?.unwrap_or_default());
-	if let Some(file_to_run) = args.file_to_run {
-		let file_to_run = env::current_dir().context("No current working directory found")?.join(file_to_run);
-		let script_location = file_to_run.canonicalize()
-			.with_context(|| format!("Couldn't find file at {}", file_to_run.display()))?;
-		run_from_fs(user_rc, script_location)
-	}
-	else {
-		todo!();
+	let limits = args.limits();
+	// Only built for commands which run scripts, so a bad allow list can't break the others.
+	let run_options = || -> Result<RunOptions> {
+		Ok(RunOptions {
+			trust_policy: args.trust_policy,
+			allow_list: AllowList::from_env_and_file(args.trusted_hashes.as_deref())?,
+			limits,
+			script_args: args.script_args.clone()
+		})
+	};
+	match args.command {
+		Some(Commands::Bless { file_to_bless, all, locked, reason }) => {
+			let script_location = locate_script(file_to_bless)?;
+			bless_from_fs(user_rc, script_location, all, locked, reason)
+		},
+		Some(Commands::Config { path, json }) => {
+			let directory = match path {
+				Some(path) => locate_script(path)?,
+				None => env::current_dir().context("No current working directory found")?
+			};
+			let directory = if directory.is_file() {
+				directory.parent().context("Ludo scripts must exist inside of a workspace")?.to_path_buf()
+			} else {
+				directory
+			};
+			print_config(&directory, &limits, json)
+		},
+		Some(Commands::Hash { binary }) => print_hash_from_fs(&binary),
+		Some(Commands::Key { command }) => match command {
+			KeyCommands::Generate { key_file, name } => generate_key(&key_file, name),
+			KeyCommands::Sign { key, binary } => sign_binary(&key, &binary),
+			KeyCommands::Verify { binary, signature, public_key } => verify_binary(&user_rc, &binary, &signature, public_key),
+			KeyCommands::Trust { public_key, name } => trust_key(public_key, name),
+			KeyCommands::Untrust { public_key } => untrust_key(public_key),
+			KeyCommands::List => list_trusted_keys(&user_rc)
+		},
+		Some(Commands::Lock { file_to_lock, check }) => {
+			let script_location = locate_script(file_to_lock)?;
+			lock_from_fs(user_rc, script_location, check)
+		},
+		Some(Commands::Blessings { name, path }) => list_blessings(&user_rc, name, path),
+		Some(Commands::Unbless { hash, all, missing, legacy }) => unbless(hash, all, missing, legacy),
+		Some(Commands::Migrate { path, user, dry_run, force }) => {
+			let path = match path {
+				Some(path) => locate_script(path)?,
+				None => env::current_dir().context("No current working directory found")?
+			};
+			migrate(&path, user, dry_run, force)
+		},
+		Some(Commands::Repl { workspace }) => run_repl(user_rc, workspace, &run_options()?),
+		None => match args.file_to_run {
+			Some(file_to_run) => {
+				let script_location = locate_script(file_to_run)?;
+				run_from_fs(user_rc, script_location, &run_options()?)
+			},
+			None => run_repl(user_rc, false, &run_options()?)
+		}
 	}
 }
+
+fn locate_script(
+	path: PathBuf
+) -> Result<PathBuf> {
+	let path = env::current_dir().context("No current working directory found")?.join(path);
+	path.canonicalize()
+		.with_context(|| format!("Couldn't find file at {}", path.display()))
+}
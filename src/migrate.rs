@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use ansi_term::Color::{Green, Yellow};
+use ansi_term::Style;
+use anyhow::{bail, Context, Result};
+use serde::de::IgnoredAny;
+use crate::ludorc::{migrate_workspace_rc, user_rc_path, UserRcStore, CURRENT_VERSION};
+
+/// Finds every .ludorc under `directory`, skipping hidden directories and symlinks.
+fn find_workspace_rcs(
+    directory: &Path,
+    found: &mut Vec<PathBuf>
+) -> Result<()> {
+    let entries = fs::read_dir(directory).with_context(|| format!("Failed to read directory {}", directory.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read directory {}", directory.display()))?;
+        let file_type = entry.file_type()?;
+        let name = entry.file_name();
+        if file_type.is_file() && name == ".ludorc" {
+            found.push(entry.path());
+        } else if file_type.is_dir() && !name.to_string_lossy().starts_with('.') {
+            find_workspace_rcs(&entry.path(), found)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `source` has comments or trailing commas, which rewriting it won't keep.
+fn has_extensions(
+    source: &str
+) -> bool {
+    serde_json::from_str::<IgnoredAny>(source).is_err()
+}
+
+/// What became of a .ludorc written in an older version of the format.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Migrated,
+    /// Not written, as `--dry-run` was given, but shown as what it would have been.
+    Previewed(String),
+    /// Left alone, as rewriting it would drop its comments or trailing commas.
+    Refused
+}
+
+/// Decides whether a .ludorc is rewritten as `contents`, recording why in `outcome`. Files with
+/// comments or trailing commas are only rewritten when `force` is set.
+fn approve(
+    source: &str,
+    contents: &[u8],
+    dry_run: bool,
+    force: bool,
+    outcome: &mut Outcome
+) -> bool {
+    *outcome = if dry_run {
+        Outcome::Previewed(String::from_utf8_lossy(contents).into_owned())
+    } else if has_extensions(source) && !force {
+        Outcome::Refused
+    } else {
+        Outcome::Migrated
+    };
+    *outcome == Outcome::Migrated
+}
+
+fn report_migration(
+    path: &Path,
+    version: u32,
+    outcome: &Outcome,
+    had_extensions: bool
+) {
+    if version == CURRENT_VERSION {
+        println!("{}", Style::new().dimmed().paint(format!("{} is already version {CURRENT_VERSION}", path.display())));
+        return;
+    }
+    match outcome {
+        Outcome::Migrated => println!("{} {} from version {version} to {CURRENT_VERSION}", Green.bold().paint("Migrated"), path.display()),
+        Outcome::Previewed(contents) => print!("{} {} from version {version} to {CURRENT_VERSION}, as:\n{contents}", Green.bold().paint("Would migrate"), path.display()),
+        Outcome::Refused => println!("{} {}, as rewriting it would drop its comments and trailing commas", Yellow.bold().paint("Skipped"), path.display())
+    }
+    if had_extensions && *outcome != Outcome::Refused {
+        println!("   {}", Yellow.paint("Its comments and trailing commas aren't kept"));
+    }
+}
+
+/// Rewrites .ludorc files in the current version of the format. `path` is either a .ludorc, or a
+/// directory in which every .ludorc is migrated. When `user` is set, only the user .ludorc is.
+/// With `dry_run`, the rewritten files are printed instead. Files with comments or trailing
+/// commas are skipped, failing the migration, unless `force` is set.
+pub fn migrate(
+    path: &Path,
+    user: bool,
+    dry_run: bool,
+    force: bool
+) -> Result<()> {
+    let mut refused = 0;
+    if user {
+        let store = UserRcStore::at_home()?;
+        let had_extensions = fs::read_to_string(store.path()).is_ok_and(|source| has_extensions(&source));
+        let mut outcome = Outcome::Migrated;
+        match store.migrate(|source, contents| approve(source, contents, dry_run, force, &mut outcome)).context("Failed to migrate user .ludorc")? {
+            Some(version) => {
+                report_migration(store.path(), version, &outcome, had_extensions);
+                refused += usize::from(version != CURRENT_VERSION && outcome == Outcome::Refused);
+            },
+            None => println!("No user .ludorc found at {}", store.path().display())
+        }
+    } else {
+        let mut found = vec![];
+        if path.is_dir() {
+            find_workspace_rcs(path, &mut found)?;
+            found.sort();
+        } else {
+            found.push(path.to_path_buf());
+        }
+        // The user .ludorc shares its name with workspace ones, but has a format of its own.
+        let user_rc_path = user_rc_path();
+        found.retain(|path| Some(path) != user_rc_path.as_ref());
+        if found.is_empty() {
+            println!("No .ludorc files found in {}", path.display());
+        }
+        for path in found {
+            let had_extensions = fs::read_to_string(&path).is_ok_and(|source| has_extensions(&source));
+            let mut outcome = Outcome::Migrated;
+            let version = migrate_workspace_rc(&path, |source, contents| approve(source, contents, dry_run, force, &mut outcome))
+                .with_context(|| format!("Failed to migrate {}", path.display()))?;
+            report_migration(&path, version, &outcome, had_extensions);
+            refused += usize::from(version != CURRENT_VERSION && outcome == Outcome::Refused);
+        }
+    }
+    if refused > 0 {
+        bail!("{refused} .ludorc file(s) weren't migrated, as they have comments or trailing commas. Check the result with --dry-run, then pass --force to rewrite them anyway");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_with_comments_are_only_rewritten_when_forced() {
+        let mut outcome = Outcome::Migrated;
+        let commented = "{ // note\n \"version\": 1 }";
+        assert!(!approve(commented, b"{}", false, false, &mut outcome));
+        assert_eq!(outcome, Outcome::Refused);
+        assert!(approve(commented, b"{}", false, true, &mut outcome));
+        assert_eq!(outcome, Outcome::Migrated);
+        assert!(approve(r#"{ "version": 1 }"#, b"{}", false, false, &mut outcome));
+    }
+
+    #[test]
+    fn dry_runs_never_write() {
+        let mut outcome = Outcome::Migrated;
+        assert!(!approve(r#"{ "version": 1 }"#, b"{}\n", true, true, &mut outcome));
+        assert_eq!(outcome, Outcome::Previewed("{}\n".into()));
+    }
+}
@@ -1,22 +1,52 @@
-use crate::fs_util::{locate_module_script, resolve_module_path, select_native_binary};
+use crate::error::{ErrorKind, LudoError};
+use crate::fs_util::{select_native_binary, write_file_atomically};
 use crate::ludorc::Native;
+use crate::permissions::{collect_permitted_workspaces, PermittedWorkspace};
 use crate::run::ScriptContext;
-use anyhow::{anyhow, bail, Context, Result};
-use base64ct::{Base64, Encoding};
+use crate::signing::decode_signature;
+use anyhow::{bail, Context, Result};
+use base64ct::{Base64, Base64Url, Encoding};
+use ed25519_dalek::Signature;
 use sha3::{Digest, Sha3_256};
-use std::collections::VecDeque;
-use std::ffi::{CString, OsString};
-use std::fmt::Display;
+use std::ffi::OsString;
 use std::fs;
+use std::fs::DirBuilder;
 use std::path::{Path, PathBuf};
-use libloading::{library_filename, Library, Symbol};
-use mlua::{lua_CFunction, Function, Lua, Value};
+use libloading::{library_filename, Library};
+use mlua::{lua_CFunction, Lua, Value};
+
+/// Hashes a native binary by its contents alone, so the same binary has the same hash wherever
+/// it's stored. This is the hash extension authors should publish.
+pub fn content_hash(
+    bytes: &[u8]
+) -> String {
+    let hash = Sha3_256::digest(bytes);
+    Base64::encode_string(hash.as_slice())
+}
+
+/// The original hash format, which also covered the library's title and absolute path. Only used
+/// to recognise blessings made before hashes were content-addressed.
+pub fn legacy_hash(
+    title: &str,
+    path: &Path,
+    bytes: &[u8]
+) -> String {
+    let mut hash = Sha3_256::new();
+    Digest::update(&mut hash, title.as_bytes());
+    Digest::update(&mut hash, path.as_os_str().as_encoded_bytes());
+    Digest::update(&mut hash, bytes);
+    Base64::encode_string(hash.finalize().as_slice())
+}
 
 #[derive(Debug, Clone)]
 pub struct BlessInfo {
     pub title: String,
     pub path: PathBuf,
-    pub hash: String
+    pub hash: String,
+    pub legacy_hash: String,
+    /// A detached signature declared alongside the native, which lets trusted publishers vouch
+    /// for binaries without each one being blessed.
+    pub signature: Option<Signature>
 }
 
 impl BlessInfo {
@@ -25,32 +55,55 @@ impl BlessInfo {
         path: PathBuf,
         bytes: &[u8]
     ) -> Self {
-        let mut hash = Sha3_256::new();
-        Digest::update(&mut hash, title.as_bytes());
-        Digest::update(&mut hash, path.as_os_str().as_encoded_bytes());
-        Digest::update(&mut hash, bytes);
-        let hash = Base64::encode_string(hash.finalize().as_slice());
-        Self { title, path, hash }
+        let hash = content_hash(bytes);
+        let legacy_hash = legacy_hash(&title, &path, bytes);
+        Self { title, path, hash, legacy_hash, signature: None }
     }
 
-    pub fn new_from_fs(
+    pub fn new_for_native(
         native: &Native,
-        workspace_path: &Path,
-    ) -> Result<Self>{
-        let name = OsString::from(&native.name);
-        let path = workspace_path.join(select_native_binary(&name, &native.parent));
-        let bytes = fs::read(&path).with_context(|| format!("Could not read native binary {} at {}", native.name, path.display()))?;
-        Ok(Self::new(native.name.to_string(), path, &bytes))
+        path: PathBuf,
+        bytes: &[u8]
+    ) -> Result<Self> {
+        let mut info = Self::new(native.name.to_string(), path, bytes);
+        if let Some(signature) = &native.signature {
+            info.signature = Some(decode_signature(signature)
+                .with_context(|| format!("Invalid signature declared for native binary {}", native.name))?);
+        }
+        Ok(info)
     }
 }
 
 pub struct TransitiveNative {
     pub context: ScriptContext,
+    /// The main script, followed by each alias whose permissions led to this native.
+    pub permission_chain: Vec<String>,
     pub native: Native,
-    pub bless: BlessInfo
+    pub bless: BlessInfo,
+    /// The contents of the binary when it was hashed. These bytes, rather than whatever is on
+    /// disk later, are what gets loaded, so the binary can't be swapped after being verified.
+    pub bytes: Vec<u8>
 }
 
 impl TransitiveNative {
+    pub fn new_from_fs(
+        context: ScriptContext,
+        native: Native,
+        permission_chain: Vec<String>
+    ) -> Result<Self> {
+        let name = OsString::from(&native.name);
+        let path = context.workspace_root().join(select_native_binary(&name, &native.parent));
+        let bytes = fs::read(&path)
+            .map_err(|err| LudoError::NativeLoad(format!("Could not read native binary {} at {}: {err}", native.name, path.display())))?;
+        let bless = BlessInfo::new_for_native(&native, path, &bytes)?;
+        Ok(Self { context, permission_chain, native, bless, bytes })
+    }
+
+    /// Describes how the main script came to be allowed to run this native, for error messages.
+    pub fn describe_permission_chain(&self) -> String {
+        self.permission_chain.join(" -> ")
+    }
+
     pub fn is_blessed(&self) -> bool {
         self.context.user_rc.is_blessed(&self.bless)
     }
@@ -60,44 +113,136 @@ pub fn collect_transitive_natives(
     main_context: &ScriptContext
 ) -> Result<Vec<TransitiveNative>> {
     let mut transitive_natives = vec![];
-    let mut queue = VecDeque::from([main_context.clone()]);
-    while let Some(context) = queue.pop_front() {
-        if let Some(native) = &context.workspace_rc.native {
-            let workspace_path = context.script_location.parent().context("Ludo scripts must exist inside of a workspace")?;
-            transitive_natives.push(TransitiveNative {
-                context: context.clone(),
-                native: native.clone(),
-                bless: BlessInfo::new_from_fs(native, workspace_path)
-                    .with_context(|| format!("Failed to bless native for {}", context.script_location.display()))?
-            });
-        }
-        for (alias, permissions) in context.workspace_rc.permissions.iter() {
-            if !permissions.native { continue }
-            let workspace_path = resolve_module_path(&context.luau_rc, &context.script_location, Path::new(alias))
-                .with_context(|| format!("Failed to resolve module path for alias {alias} (defined for {})", context.script_location.display()))?;
-            let script_location = locate_module_script(&workspace_path)
-                .with_context(|| format!("Failed to locate script for alias {alias} (defined for {})", context.script_location.display()))?
-                .with_context(|| format!("No script associated with alias {alias} (defined for {}", context.script_location.display()))?;
-            let sub_context = ScriptContext::new_from_fs(main_context.user_rc.clone(), script_location)
-                .with_context(|| format!("Failed to construct script context for alias {alias} (defined for {})", context.script_location.display()))?;
-            queue.push_back(sub_context);
-        }
+    for PermittedWorkspace { context, chain } in collect_permitted_workspaces(main_context, |x| x.native)? {
+        let Some(native) = &context.workspace_rc.native else { continue };
+        let transitive_native = TransitiveNative::new_from_fs(context.clone(), native.clone(), chain.clone())
+            .with_context(|| format!("Failed to bless native for {} (permitted via {})", context.script_location.display(), chain.join(" -> ")))?;
+        transitive_natives.push(transitive_native);
     }
     Ok(transitive_natives)
 }
 
-pub unsafe fn load_native_library(
-    lua: &Lua,
-    native: &Native
+/// Native libraries stay loaded for as long as the Luau state which might call into them.
+struct LoadedLibraries(Vec<Library>);
+
+/// Refuses a staging directory which another user owns or could write to, as they could swap out
+/// a binary between it being staged and being loaded.
+#[cfg(unix)]
+fn check_private_directory(
+    path: &Path
 ) -> Result<()> {
-    let library_path = &native.parent.join(library_filename(&native.name));
-    let Ok(library) = Library::new(library_path)
-        else { bail!("Failed to open native library at {}", library_path.display()) };
-    let Ok(ext_main) = library.get::<lua_CFunction>(&native.entry_point.to_bytes())
-        else { bail!("Failed to locate entry point {} in library {}", native.entry_point.to_string_lossy(), library_path.display()) };
-    let Ok(exports) = lua.exec_raw::<Value>((), |state| unsafe { ext_main(state); })
-        else { bail!("Failed to execute entry point {} in library {}", native.entry_point.to_string_lossy(), library_path.display()) };
-    let Ok(_) = lua.globals().set("native", exports)
-        else { bail!("Failed to assign native global for library {}", library_path.display()) };
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to inspect native staging directory at {}", path.display()))?;
+    if !metadata.is_dir() {
+        bail!("Native staging directory at {} is not a directory", path.display());
+    }
+    if metadata.uid() != unsafe { libc::geteuid() } {
+        bail!("Native staging directory at {} is owned by another user", path.display());
+    }
+    if metadata.mode() & 0o022 != 0 {
+        bail!("Native staging directory at {} is writable by other users", path.display());
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Copies verified native binary contents into a private, content-addressed cache, so the file
+/// handed to the dynamic loader is one nobody else can have swapped out.
+pub fn stage_native_binary(
+    transitive_native: &TransitiveNative
+) -> Result<PathBuf> {
+    let cache_dir = dirs::cache_dir().context("No cache directory found to stage native binaries in")?;
+    let digest = Base64::decode_vec(&transitive_native.bless.hash).context("Native hash must be valid base64")?;
+    let natives_dir = cache_dir.join("ludo").join("natives");
+    let staging_dir = natives_dir.join(Base64Url::encode_string(&digest));
+    let mut dir_builder = DirBuilder::new();
+    dir_builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        dir_builder.mode(0o700);
+    }
+    dir_builder.create(&staging_dir)
+        .with_context(|| format!("Failed to create native staging directory at {}", staging_dir.display()))?;
+    #[cfg(unix)]
+    for directory in [&cache_dir.join("ludo"), &natives_dir, &staging_dir] {
+        check_private_directory(directory)?;
+    }
+    let staged_path = staging_dir.join(library_filename(&transitive_native.native.name));
+    let already_staged = fs::read(&staged_path).is_ok_and(|bytes| content_hash(&bytes) == transitive_native.bless.hash);
+    if !already_staged {
+        write_file_atomically(&staged_path, &transitive_native.bytes)
+            .with_context(|| format!("Failed to stage native binary at {}", staged_path.display()))?;
+    }
+    Ok(staged_path)
+}
+
+/// Loads a verified native library, returning whatever its entry point exports.
+pub unsafe fn load_native_library(
+    lua: &Lua,
+    transitive_native: &TransitiveNative
+) -> Result<Value> {
+    let native = &transitive_native.native;
+    let library_path = &stage_native_binary(transitive_native).map_err(|err| LudoError::wrap(ErrorKind::NativeLoad, err))?;
+    let Ok(library) = (unsafe { Library::new(library_path) })
+        else { bail!(LudoError::NativeLoad(format!("Failed to open native library at {}", library_path.display()))) };
+    let Ok(ext_main) = (unsafe { library.get::<lua_CFunction>(native.entry_point.to_bytes()) })
+        else { bail!(LudoError::NativeLoad(format!("Failed to locate entry point {} in library {}", native.entry_point.to_string_lossy(), library_path.display()))) };
+    let Ok(exports) = (unsafe { lua.exec_raw::<Value>((), |state| { ext_main(state); }) })
+        else { bail!(LudoError::NativeLoad(format!("Failed to execute entry point {} in library {}", native.entry_point.to_string_lossy(), library_path.display()))) };
+    match lua.app_data_mut::<LoadedLibraries>() {
+        Some(mut loaded) => loaded.0.push(library),
+        None => { lua.set_app_data(LoadedLibraries(vec![library])); }
+    }
+    Ok(exports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+    use std::rc::Rc;
+    use crate::ludorc::UserRc;
+
+    #[test]
+    fn content_hash_ignores_location() {
+        let a = BlessInfo::new("foo".into(), PathBuf::from("/home/a/libfoo.so"), b"binary");
+        let b = BlessInfo::new("foo".into(), PathBuf::from("/home/b/libfoo.so"), b"binary");
+        assert_eq!(a.hash, b.hash);
+        assert_ne!(a.legacy_hash, b.legacy_hash);
+    }
+
+    #[test]
+    fn transitive_natives_record_their_permission_chain() {
+        let binary = library_filename("inner").to_string_lossy().into_owned();
+        let dir = TempDir::with_files("permission-chain", &[
+            ("main/.luaurc", r#"{ "aliases": { "outer": "../outer" } }"#),
+            ("main/.ludorc", r#"{ "version": 1, "permissions": { "@outer": { "native": true } } }"#),
+            ("main/init.luau", ""),
+            ("outer/.luaurc", r#"{ "aliases": { "inner": "../inner" } }"#),
+            ("outer/.ludorc", r#"{ "version": 1, "permissions": { "@inner": { "native": true } } }"#),
+            ("outer/init.luau", ""),
+            ("inner/.ludorc", r#"{ "version": 1, "native": { "name": "inner", "parent": ".", "entry_point": "luaopen_inner" } }"#),
+            ("inner/init.luau", ""),
+            (&format!("inner/{binary}"), "not really a library")
+        ]);
+        let main_script = dir.join("main/init.luau");
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), main_script.clone()).unwrap();
+        let transitive_natives = collect_transitive_natives(&context).unwrap();
+        assert_eq!(transitive_natives.len(), 1);
+        assert_eq!(transitive_natives[0].permission_chain, [main_script.display().to_string(), "@outer".into(), "@inner".into()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_staging_directories_are_refused() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new("staging-permissions");
+        fs::set_permissions(&*dir, fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(check_private_directory(&dir).is_ok());
+        fs::set_permissions(&*dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(check_private_directory(&dir).is_err(), "World-writable directories should be refused");
+        fs::set_permissions(&*dir, fs::Permissions::from_mode(0o770)).unwrap();
+        assert!(check_private_directory(&dir).is_err(), "Group-writable directories should be refused");
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use crate::error::{ErrorKind, LudoError};
+use crate::fs_util::{canonicalise_path, locate_module_script, resolve_module_path};
+use crate::luaurc::load_composite_luau_rc;
+use crate::ludorc::{Grant, Permissions};
+use crate::run::ScriptContext;
+
+/// A workspace which some chain of `.ludorc` grants from the main script allows to do something.
+pub struct PermittedWorkspace {
+    pub context: ScriptContext,
+    /// The main script, followed by each alias whose permissions led to this workspace.
+    pub chain: Vec<String>
+}
+
+/// Finds the script context of the package `grant` names. Its alias is resolved from the .ludorc
+/// which made the grant, so relative paths and .luaurc aliases mean what they do there, even when
+/// the grant is inherited by a nested workspace.
+fn resolve_grant(
+    context: &ScriptContext,
+    grant: &Grant
+) -> Result<ScriptContext> {
+    let Grant { alias, granted_in, .. } = grant;
+    let ludorc_path = granted_in.join(".ludorc");
+    let luau_rc = load_composite_luau_rc(granted_in)
+        .with_context(|| format!("Failed to construct .luaurc for alias {alias} (granted in {})", ludorc_path.display()))?;
+    let workspace_path = resolve_module_path(&luau_rc, &ludorc_path, Path::new(alias))
+        .with_context(|| format!("Failed to resolve module path for alias {alias} (granted in {})", ludorc_path.display()))
+        .map_err(|err| LudoError::wrap(ErrorKind::Resolution, err))?;
+    let script_location = locate_module_script(&workspace_path)
+        .with_context(|| format!("Failed to locate script for alias {alias} (granted in {})", ludorc_path.display()))
+        .map_err(|err| LudoError::wrap(ErrorKind::Resolution, err))?
+        .ok_or_else(|| LudoError::Resolution(format!("No script associated with alias {alias} (granted in {})", ludorc_path.display())))?;
+    ScriptContext::new_from_fs(context.user_rc.clone(), script_location)
+        .with_context(|| format!("Failed to construct script context for alias {alias} (granted in {})", ludorc_path.display()))
+}
+
+/// Walks the permissions granted from the main script's workspace, following only grants for
+/// which `is_granted` holds. The main script's workspace is always permitted.
+pub fn collect_permitted_workspaces(
+    main_context: &ScriptContext,
+    is_granted: impl Fn(&Permissions) -> bool
+) -> Result<Vec<PermittedWorkspace>> {
+    let mut permitted = vec![];
+    let mut visited = HashSet::new();
+    let main_chain = vec![main_context.script_location.display().to_string()];
+    let mut queue = VecDeque::from([(main_context.clone(), main_chain)]);
+    while let Some((context, chain)) = queue.pop_front() {
+        if !visited.insert(context.workspace_root().to_path_buf()) { continue }
+        for grant in context.workspace_rc.grants() {
+            if !is_granted(&grant.permissions) { continue }
+            let sub_context = resolve_grant(&context, &grant)?;
+            let mut sub_chain = chain.clone();
+            sub_chain.push(grant.alias);
+            queue.push_back((sub_context, sub_chain));
+        }
+        permitted.push(PermittedWorkspace { context, chain });
+    }
+    Ok(permitted)
+}
+
+/// Finds which of the `permitted` workspace roots the script described by `context` belongs to.
+/// Scripts without a .ludorc of their own belong to the nearest permitted directory above them.
+pub fn find_permitted_workspace<'a>(
+    permitted: impl IntoIterator<Item = &'a PathBuf>,
+    context: &ScriptContext
+) -> Option<&'a PathBuf> {
+    let root = context.workspace_root();
+    permitted.into_iter()
+        .filter(|permitted| root == *permitted || context.workspace_rc.root.is_none() && root.starts_with(permitted))
+        .max_by_key(|permitted| permitted.components().count())
+}
+
+/// The parts of the filesystem a workspace may use through `@ludo/fs`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsAccess {
+    /// The main script's workspace may use the whole filesystem, just as its user could.
+    Unrestricted,
+    /// Other packages may only use the canonical directories granted to them, and their contents.
+    Directories(Vec<PathBuf>)
+}
+
+impl FsAccess {
+    pub fn allows(
+        &self,
+        path: &Path
+    ) -> bool {
+        match self {
+            Self::Unrestricted => true,
+            Self::Directories(directories) => directories.iter().any(|directory| path.starts_with(directory))
+        }
+    }
+}
+
+/// Works out which directories each workspace may use. Directories in `fs` grants are relative to
+/// the granting .ludorc, and a workspace can only grant directories it may use itself.
+pub fn collect_fs_access(
+    main_context: &ScriptContext
+) -> Result<HashMap<PathBuf, FsAccess>> {
+    let mut access = HashMap::from([(main_context.workspace_root().to_path_buf(), FsAccess::Unrestricted)]);
+    for PermittedWorkspace { context, .. } in collect_permitted_workspaces(main_context, |x| !x.fs.is_empty())? {
+        let root = context.workspace_root();
+        for grant in context.workspace_rc.grants() {
+            if grant.permissions.fs.is_empty() { continue }
+            let grantee_root = resolve_grant(&context, &grant)?.workspace_root().to_path_buf();
+            let alias = &grant.alias;
+            for directory in &grant.permissions.fs {
+                let directory = canonicalise_path(&root.join(directory))
+                    .with_context(|| format!("Failed to resolve directory {} granted to {alias} (granted in {})", directory.display(), grant.granted_in.join(".ludorc").display()))?;
+                if !access.get(root).is_some_and(|x| x.allows(&directory)) {
+                    bail!(LudoError::PermissionDenied(format!("{} grants {alias} access to {}, which it may not access itself", root.join(".ludorc").display(), directory.display())));
+                }
+                match access.entry(grantee_root.clone()).or_insert(FsAccess::Directories(vec![])) {
+                    FsAccess::Unrestricted => (),
+                    FsAccess::Directories(directories) => directories.push(directory)
+                }
+            }
+        }
+    }
+    Ok(access)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::fs_util::TempDir;
+    use crate::ludorc::UserRc;
+
+    fn process_roots(
+        dir: &Path
+    ) -> Vec<PathBuf> {
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), dir.join("init.luau")).unwrap();
+        let permitted = collect_permitted_workspaces(&context, |x| x.process).expect("Grants should resolve");
+        permitted.iter().map(|x| x.context.workspace_root().to_path_buf()).collect()
+    }
+
+    #[test]
+    fn inherited_relative_grants_resolve_from_their_ludorc() {
+        let dir = TempDir::with_files("grant-relative", &[
+            (".ludorc", r#"{ "version": 2, "permissions": { "./vendor/dep": { "process": true } } }"#),
+            ("init.luau", r#"return require("./vendor/dep")"#),
+            ("vendor/dep/.ludorc", r#"{ "version": 2 }"#),
+            ("vendor/dep/init.luau", "return nil")
+        ]);
+        assert_eq!(process_roots(&dir), [dir.to_path_buf(), dir.join("vendor/dep")]);
+    }
+
+    #[test]
+    fn inherited_alias_grants_ignore_inner_luaurc() {
+        let dir = TempDir::with_files("grant-alias", &[
+            (".luaurc", r#"{ "aliases": { "dep": "./vendor/dep" } }"#),
+            (".ludorc", r#"{ "version": 2, "permissions": { "@dep": { "process": true } } }"#),
+            ("init.luau", r#"return require("@dep")"#),
+            ("vendor/dep/.luaurc", r#"{ "aliases": { "dep": "./other" } }"#),
+            ("vendor/dep/.ludorc", r#"{ "version": 2 }"#),
+            ("vendor/dep/init.luau", "return nil"),
+            ("vendor/dep/other/.ludorc", r#"{ "version": 2 }"#),
+            ("vendor/dep/other/init.luau", "return nil")
+        ]);
+        assert_eq!(process_roots(&dir), [dir.to_path_buf(), dir.join("vendor/dep")], "@dep should mean what it does where it was granted");
+    }
+}
@@ -0,0 +1,208 @@
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use mlua::{ExternalResult, Function, Lua, Table};
+use thiserror::Error;
+
+/// Name scripts require the process library by.
+pub const PROCESS_MODULE: &str = "@ludo/process";
+
+/// Kept with the Luau state by `process.exit`, which then yields so the script goes no further.
+/// Where it can't yield, it raises this instead, and the script still exits with this status if
+/// it catches the error.
+#[derive(Error, Debug, Clone, Copy, PartialEq)]
+#[error("Script exited with status {0}")]
+pub struct ExitRequested(pub u8);
+
+/// Kept with the Luau state while `process.exit` yields, so the interrupt lets it.
+struct ExitYielding;
+
+impl ExitRequested {
+    /// The exit the script running in `lua` asked for, if it called `process.exit`.
+    pub fn get(
+        lua: &Lua
+    ) -> Option<Self> {
+        lua.app_data_ref::<Self>().map(|exit| *exit)
+    }
+
+    /// Called by Luau as it runs. Once the script has asked to exit this keeps failing, so it
+    /// can't carry on by catching the error with `pcall`, except to let `process.exit` yield.
+    pub fn check(
+        lua: &Lua
+    ) -> mlua::Result<()> {
+        if lua.remove_app_data::<ExitYielding>().is_some() {
+            return Ok(());
+        }
+        match Self::get(lua) {
+            Some(exit) => Err(mlua::Error::external(exit)),
+            None => Ok(())
+        }
+    }
+}
+
+/// Runs a program to completion, returning its exit status and captured output.
+fn spawn(
+    lua: &Lua,
+    (program, args, options): (String, Option<Vec<String>>, Option<Table>)
+) -> mlua::Result<Table> {
+    let mut command = Command::new(&program);
+    command.args(args.unwrap_or_default());
+    let mut input = None;
+    if let Some(options) = options {
+        if let Some(cwd) = options.get::<Option<String>>("cwd")? {
+            command.current_dir(cwd);
+        }
+        if let Some(env) = options.get::<Option<Table>>("env")? {
+            for pair in env.pairs::<String, String>() {
+                let (key, value) = pair?;
+                command.env(key, value);
+            }
+        }
+        input = options.get::<Option<mlua::String>>("stdin")?;
+    }
+    command.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() });
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let mut child = command.spawn()
+        .map_err(|err| format!("Failed to spawn {program}: {err}"))
+        .into_lua_err()?;
+    // Write the input on its own thread while the output is read, so a program which fills its
+    // output pipes before it has read all of its input can't stall both sides.
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => {
+            let input = input.as_bytes().to_vec();
+            Some(thread::spawn(move || stdin.write_all(&input)))
+        },
+        _ => None
+    };
+    let output = child.wait_with_output()
+        .map_err(|err| format!("Failed to wait for {program}: {err}"))
+        .into_lua_err()?;
+    if let Some(writer) = writer {
+        writer.join()
+            .map_err(|_| format!("Failed to write to standard input of {program}"))
+            .into_lua_err()?
+            .map_err(|err| format!("Failed to write to standard input of {program}: {err}"))
+            .into_lua_err()?;
+    }
+
+    let result = lua.create_table()?;
+    result.set("status", output.status.code())?;
+    result.set("success", output.status.success())?;
+    result.set("stdout", lua.create_string(&output.stdout)?)?;
+    result.set("stderr", lua.create_string(&output.stderr)?)?;
+    Ok(result)
+}
+
+/// Creates the `@ludo/process` library, giving access to the script's arguments, its environment
+/// and subprocesses.
+pub fn create_process_library(
+    lua: &Lua,
+    script_args: &[String]
+) -> mlua::Result<Table> {
+    let library = lua.create_table()?;
+
+    let args = lua.create_sequence_from(script_args.iter().map(String::as_str))?;
+    args.set_readonly(true);
+    library.set("args", args)?;
+
+    let environment = lua.create_table()?;
+    for (key, value) in env::vars_os() {
+        let (Some(key), Some(value)) = (key.to_str(), value.to_str()) else { continue };
+        environment.set(key, value)?;
+    }
+    environment.set_readonly(true);
+    library.set("env", environment)?;
+
+    library.set("cwd", lua.create_function(|_, ()| {
+        let cwd = env::current_dir()
+            .map_err(|err| format!("No current working directory found: {err}"))
+            .into_lua_err()?;
+        Ok(cwd.to_string_lossy().into_owned())
+    })?)?;
+
+    // Yielding has to happen in Luau, so this records the exit and then yields the running thread,
+    // which the scheduler never resumes. Where the thread can't yield, the error stops it, and the
+    // interrupt `Limiter` installs keeps stopping it if the error is caught, as it does if the
+    // yield only reaches a coroutine the script resumed itself.
+    let request_exit = lua.create_function(|lua, (status, yieldable): (Option<u8>, bool)| -> mlua::Result<()> {
+        let exit = ExitRequested(status.unwrap_or(0));
+        lua.set_app_data(exit);
+        if !yieldable {
+            return Err(mlua::Error::external(exit));
+        }
+        lua.set_app_data(ExitYielding);
+        Ok(())
+    })?;
+    let exit: Function = lua.load(r#"
+        local requestExit = ...
+        return function(status)
+            requestExit(status, coroutine.isyieldable())
+            coroutine.yield()
+        end
+    "#).set_name("=process").call(request_exit)?;
+    library.set("exit", exit)?;
+
+    library.set("spawn", lua.create_function(spawn)?)?;
+
+    library.set_readonly(true);
+    Ok(library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::MultiValue;
+    use crate::limits::{Limiter, Limits};
+    use crate::task::{create_task_library, run_to_completion, Scheduler};
+
+    #[cfg(unix)]
+    #[test]
+    fn spawn_captures_output_and_status() {
+        let lua = Lua::new();
+        let process = create_process_library(&lua, &["first".into()]).unwrap();
+        lua.globals().set("process", process).unwrap();
+        let result: (String, String, i32, bool) = lua.load(r#"
+            local result = process.spawn("sh", { "-c", "cat; echo oops >&2; exit 3" }, { stdin = process.args[1] })
+            return result.stdout, result.stderr, result.status, result.success
+        "#).eval().unwrap();
+        assert_eq!(result, ("first".into(), "oops\n".into(), 3, false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spawn_passes_large_input_through_while_reading_output() {
+        let lua = Lua::new();
+        lua.globals().set("process", create_process_library(&lua, &[]).unwrap()).unwrap();
+        let length: usize = lua.load(r#"
+            return #process.spawn("cat", {}, { stdin = string.rep("x", 1000000) }).stdout
+        "#).eval().unwrap();
+        assert_eq!(length, 1_000_000);
+    }
+
+    #[test]
+    fn exit_stops_the_script_even_when_caught() {
+        for source in [
+            r#"pcall(process.exit, 3) ran = true"#,
+            r#"pcall(process.exit, 3) while true do pcall(function() end) end"#,
+            r#"coroutine.wrap(function() pcall(process.exit, 3) end)() pcall(function() end) ran = true"#,
+            r#"table.sort({ 2, 1 }, function(a, b) pcall(process.exit, 3) return a < b end) pcall(function() end) ran = true"#
+        ] {
+            let lua = Lua::new();
+            let scheduler = Scheduler::new();
+            lua.globals().set("task", create_task_library(&lua, &scheduler).unwrap()).unwrap();
+            lua.globals().set("process", create_process_library(&lua, &[]).unwrap()).unwrap();
+            Limiter::install(&lua, Limits::default()).unwrap();
+            let function = lua.load(format!(r#"
+                task.defer(function() error("pending tasks should not run") end)
+                {source}
+            "#)).into_function().unwrap();
+            let err = run_to_completion(&lua, &scheduler, function, MultiValue::new()).unwrap_err();
+            assert!(err.to_string().contains("status 3"), "Unexpected error: {err}");
+            assert_eq!(ExitRequested::get(&lua), Some(ExitRequested(3)));
+            assert_eq!(lua.globals().get::<Option<bool>>("ran").unwrap(), None, "Code after the pcall shouldn't run: {source}");
+        }
+    }
+}
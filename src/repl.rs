@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use ansi_term::Color::Red;
+use ansi_term::Style;
+use anyhow::{Context, Result};
+use mlua::{Lua, MultiValue, Table, Value};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use crate::diagnostics::format_lua_error;
+use crate::ludorc::UserRc;
+use crate::process::ExitRequested;
+use crate::require::create_module_environment;
+use crate::run::{create_runtime, ChunkRoot, RunOptions, Runtime, ScriptContext, ScriptExit};
+use crate::task::{run_to_completion, Scheduler};
+
+const CHUNK_NAME: &str = "=repl";
+
+/// Tables which format to fewer characters than this are printed on a single line.
+const INLINE_TABLE_WIDTH: usize = 60;
+
+fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|data_dir| data_dir.join("ludo").join("repl_history"))
+}
+
+fn is_identifier(
+    key: &str
+) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn format_key(
+    key: &Value,
+    seen: &mut Vec<Table>
+) -> String {
+    match key {
+        Value::String(key) => match key.to_str() {
+            Ok(key) if is_identifier(&key) => key.to_string(),
+            _ => format!("[{}]", format_value(&Value::String(key.clone()), 0, seen))
+        },
+        key => format!("[{}]", format_value(key, 0, seen))
+    }
+}
+
+fn format_table(
+    table: &Table,
+    indent: usize,
+    seen: &mut Vec<Table>
+) -> String {
+    if seen.contains(table) {
+        return "<cycle>".into();
+    }
+    seen.push(table.clone());
+    let length = table.raw_len();
+    let mut entries = vec![];
+    let mut keyed_entries = vec![];
+    for (key, value) in table.pairs::<Value, Value>().flatten() {
+        let value = format_value(&value, indent + 1, seen);
+        match key {
+            Value::Integer(index) if index >= 1 && index as usize <= length => entries.push((index, value)),
+            key => keyed_entries.push((format_key(&key, seen), value))
+        }
+    }
+    seen.pop();
+    entries.sort_by_key(|(index, _)| *index);
+    keyed_entries.sort();
+
+    let entries: Vec<_> = entries.into_iter()
+        .map(|(_, value)| value)
+        .chain(keyed_entries.into_iter().map(|(key, value)| format!("{key} = {value}")))
+        .collect();
+    if entries.is_empty() {
+        return "{}".into();
+    }
+    let inline = format!("{{ {} }}", entries.join(", "));
+    if inline.len() <= INLINE_TABLE_WIDTH && !inline.contains('\n') {
+        return inline;
+    }
+    let padding = "    ".repeat(indent + 1);
+    let entries: Vec<_> = entries.iter().map(|entry| format!("{padding}{entry}")).collect();
+    format!("{{\n{}\n{}}}", entries.join(",\n"), "    ".repeat(indent))
+}
+
+/// Formats a value for display, expanding plain tables into Luau table syntax.
+fn format_value(
+    value: &Value,
+    indent: usize,
+    seen: &mut Vec<Table>
+) -> String {
+    match value {
+        Value::String(string) => format!("{:?}", string.to_string_lossy()),
+        Value::Table(table) if table.metatable().is_none() => format_table(table, indent, seen),
+        value => value.to_string().unwrap_or_else(|_| format!("{value:?}"))
+    }
+}
+
+fn print_results(
+    results: MultiValue
+) {
+    if results.is_empty() {
+        return;
+    }
+    let formatted: Vec<_> = results.iter().map(|value| format_value(value, 0, &mut vec![])).collect();
+    println!("{}", formatted.join("\t"));
+}
+
+/// Evaluates a chunk typed into the REPL, along with any tasks it schedules. It's tried as an
+/// expression first so its value can be printed, then as a block of statements.
+fn evaluate(
+    lua: &Lua,
+    scheduler: &Rc<RefCell<Scheduler>>,
+    environment: &Table,
+    source: &str
+) -> mlua::Result<MultiValue> {
+    let expression = lua.load(format!("return {source}"))
+        .set_name(CHUNK_NAME)
+        .set_environment(environment.clone())
+        .into_function();
+    let function = match expression {
+        Ok(function) => function,
+        Err(_) => lua.load(source)
+            .set_name(CHUNK_NAME)
+            .set_environment(environment.clone())
+            .into_function()?
+    };
+    run_to_completion(lua, scheduler, function, MultiValue::new())
+}
+
+/// Opens an interactive Luau prompt in the current directory. When `load_workspace` is set, the
+/// current directory's .ludorc and .luaurc apply as they would to a script there. Calling
+/// `process.exit` closes the prompt with that status.
+pub fn run_repl(
+    user_rc: Rc<UserRc>,
+    load_workspace: bool,
+    options: &RunOptions
+) -> Result<()> {
+    let current_dir = env::current_dir().context("No current working directory found")?;
+    let script_location = current_dir.join("repl");
+    let context = if load_workspace {
+        ScriptContext::new_from_fs(user_rc, script_location).context("Failed to construct script context")?
+    } else {
+        let chunk_root = ChunkRoot { prefix: None, directory: current_dir };
+        ScriptContext { user_rc, workspace_rc: Default::default(), luau_rc: Default::default(), script_location, chunk_root }
+    };
+    let Runtime { lua, loader, scheduler, limiter } = create_runtime(&context, options)?;
+    let environment = create_module_environment(&lua, &loader, &context)
+        .map_err(|err| anyhow::anyhow!("{err}"))
+        .context("Failed to create REPL environment")?;
+
+    let mut editor = DefaultEditor::new().context("Failed to open line editor")?;
+    let history_path = history_path();
+    if let Some(history_path) = &history_path {
+        let _ = editor.load_history(history_path);
+    }
+    println!("ludo {}", env!("CARGO_PKG_VERSION"));
+    println!("{}", Style::new().dimmed().paint("Press Ctrl+D to exit, or Ctrl+C to clear the current input."));
+
+    let mut buffer = String::new();
+    let mut exit_status = 0;
+    loop {
+        let prompt = if buffer.is_empty() { "> " } else { ">> " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if buffer.trim().is_empty() {
+                    buffer.clear();
+                    continue;
+                }
+                limiter.start();
+                scheduler.borrow_mut().set_deadline(limiter.deadline());
+                let result = evaluate(&lua, &scheduler, &environment, &buffer);
+                if let Some(ExitRequested(status)) = ExitRequested::get(&lua) {
+                    let _ = editor.add_history_entry(buffer.trim_end());
+                    exit_status = status;
+                    break;
+                }
+                match result {
+                    Err(mlua::Error::SyntaxError { incomplete_input: true, .. }) => continue,
+                    Ok(results) => print_results(results),
+                    Err(err) => match limiter.exceeded(Some(&err)) {
+                        Some(limit) => eprintln!("{}", Red.paint(limit.to_string())),
+                        None => eprintln!("{}", format_lua_error(&err, loader.borrow().chunk_paths()))
+                    }
+                }
+                let _ = editor.add_history_entry(buffer.trim_end());
+                buffer.clear();
+            },
+            Err(ReadlineError::Interrupted) => buffer.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err).context("Failed to read from the terminal")
+        }
+    }
+
+    if let Some(history_path) = &history_path {
+        if let Some(parent) = history_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = editor.save_history(history_path);
+    }
+    if exit_status != 0 {
+        return Err(ScriptExit(exit_status).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(
+        source: &str
+    ) -> String {
+        let lua = Lua::new();
+        let value: Value = lua.load(source).eval().unwrap();
+        format_value(&value, 0, &mut vec![])
+    }
+
+    #[test]
+    fn format_tables_inline() {
+        assert_eq!(format(r#"{ 1, 2, "three", name = "ludo", ["two words"] = true }"#),
+            r#"{ 1, 2, "three", ["two words"] = true, name = "ludo" }"#);
+        assert_eq!(format("{}"), "{}");
+    }
+
+    #[test]
+    fn format_long_tables_across_lines() {
+        let formatted = format(r#"{ first = string.rep("a", 30), second = { string.rep("b", 30) } }"#);
+        let a = "a".repeat(30);
+        let b = "b".repeat(30);
+        assert_eq!(formatted, format!("{{\n    first = \"{a}\",\n    second = {{ \"{b}\" }}\n}}"));
+    }
+
+    #[test]
+    fn format_cyclic_tables() {
+        assert_eq!(format("local t = {} t.self = t return t"), "{ self = <cycle> }");
+    }
+
+    #[test]
+    fn evaluate_keeps_globals_between_chunks() {
+        let lua = Lua::new();
+        let scheduler = Scheduler::new();
+        let environment = lua.create_table().unwrap();
+        evaluate(&lua, &scheduler, &environment, "x = 40").unwrap();
+        let results = evaluate(&lua, &scheduler, &environment, "x + 2").unwrap();
+        assert_eq!(results.into_iter().next().and_then(|x| x.as_i64()), Some(42));
+        let incomplete = evaluate(&lua, &scheduler, &environment, "function f()\n");
+        assert!(matches!(incomplete, Err(mlua::Error::SyntaxError { incomplete_input: true, .. })));
+    }
+}
@@ -0,0 +1,635 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use anyhow::Context;
+use mlua::{ChunkMode, Function, Lua, MultiValue, Table, Value};
+use crate::error::{lua_error, ErrorKind, LudoError};
+use crate::fs_util::{locate_module_script, resolve_module_path};
+use crate::ludorc::UserRc;
+use crate::native::{load_native_library, TransitiveNative};
+use crate::filesystem::{create_fs_library, FS_MODULE};
+use crate::permissions::{find_permitted_workspace, FsAccess};
+use crate::process::{create_process_library, PROCESS_MODULE};
+use crate::run::{ChunkRoot, ScriptContext};
+
+/// Loads the Luau modules of a running script.
+///
+/// Every workspace gets its own environment table, which holds the exports of the workspace's
+/// native library as `native`. Native libraries are only loaded once their workspace's first
+/// module is required. Each module then gets an environment of its own on top of its
+/// workspace's, so it can't write globals into other modules, and only modules inside the
+/// workspace which declared a native library can see it.
+pub struct ModuleLoader {
+    user_rc: Rc<UserRc>,
+    natives: HashMap<PathBuf, TransitiveNative>,
+    native_exports: HashMap<PathBuf, Value>,
+    workspace_environments: HashMap<PathBuf, Table>,
+    /// Globals declared in .luaurc files, shared by every module of a workspace.
+    shared_globals: HashMap<PathBuf, Table>,
+    /// Results of modules which finished loading, keyed by their canonical path.
+    modules: HashMap<PathBuf, Value>,
+    /// Modules which are still loading, in the order they were required.
+    loading: Vec<PathBuf>,
+    /// Workspaces allowed to require `@ludo/process`.
+    process_permitted: HashSet<PathBuf>,
+    /// Which parts of the filesystem each workspace may use through `@ludo/fs`.
+    fs_access: HashMap<PathBuf, FsAccess>,
+    builtins: HashMap<(&'static str, PathBuf), Value>,
+    script_args: Vec<String>,
+    /// The file behind each chunk name, so errors can show the source they point at.
+    chunk_paths: HashMap<String, PathBuf>
+}
+
+impl ModuleLoader {
+    pub fn new(
+        user_rc: Rc<UserRc>
+    ) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            user_rc,
+            natives: HashMap::new(),
+            native_exports: HashMap::new(),
+            workspace_environments: HashMap::new(),
+            shared_globals: HashMap::new(),
+            modules: HashMap::new(),
+            loading: vec![],
+            process_permitted: HashSet::new(),
+            fs_access: HashMap::new(),
+            builtins: HashMap::new(),
+            script_args: vec![],
+            chunk_paths: HashMap::new()
+        }))
+    }
+
+    /// The files behind the chunks loaded so far, keyed by the name they appear by in tracebacks.
+    pub fn chunk_paths(&self) -> &HashMap<String, PathBuf> {
+        &self.chunk_paths
+    }
+
+    /// Allows modules in the workspace at `root` to require `@ludo/process`.
+    pub fn permit_process(
+        &mut self,
+        root: PathBuf
+    ) {
+        self.process_permitted.insert(root);
+    }
+
+    pub fn set_fs_access(
+        &mut self,
+        fs_access: HashMap<PathBuf, FsAccess>
+    ) {
+        self.fs_access = fs_access;
+    }
+
+    pub fn set_script_args(
+        &mut self,
+        script_args: Vec<String>
+    ) {
+        self.script_args = script_args;
+    }
+
+    /// Exposes `exports` as the `native` global to every module in the workspace at `root`, in
+    /// place of loading its native library.
+    pub fn set_native_exports(
+        &mut self,
+        root: PathBuf,
+        exports: Value
+    ) {
+        self.native_exports.insert(root, exports);
+    }
+
+    /// Registers a verified native library, to be loaded when its workspace is first required.
+    pub fn add_native(
+        &mut self,
+        transitive_native: TransitiveNative
+    ) {
+        let root = transitive_native.context.workspace_root().to_path_buf();
+        self.natives.entry(root).or_insert(transitive_native);
+    }
+
+    /// Whether some chain of permissions from the main script allows the native library declared
+    /// for this script's workspace, if it declares one.
+    fn is_native_permitted(
+        &self,
+        context: &ScriptContext
+    ) -> bool {
+        context.workspace_rc.native.is_none() || self.natives.contains_key(context.workspace_root())
+    }
+
+    fn workspace_environment(
+        &mut self,
+        lua: &Lua,
+        context: &ScriptContext
+    ) -> mlua::Result<Table> {
+        let root = context.workspace_root();
+        if let Some(environment) = self.workspace_environments.get(root) {
+            return Ok(environment.clone());
+        }
+        let transitive_native = self.natives.get(root).filter(|_| !self.native_exports.contains_key(root));
+        if let Some(transitive_native) = transitive_native {
+            let exports = unsafe { load_native_library(lua, transitive_native) }
+                .with_context(|| format!("Failed to load native library {} (permitted via {})",
+                    transitive_native.native.name,
+                    transitive_native.describe_permission_chain()
+                ))
+                .map_err(|err| LudoError::wrap(ErrorKind::NativeLoad, err))?;
+            self.set_native_exports(root.to_path_buf(), exports);
+        }
+        let environment = lua.create_table()?;
+        if let Some(exports) = self.native_exports.get(root) {
+            environment.raw_set("native", exports)?;
+        }
+        let metatable = lua.create_table()?;
+        metatable.raw_set("__index", lua.globals())?;
+        environment.set_metatable(Some(metatable));
+        environment.set_readonly(true);
+        self.workspace_environments.insert(root.to_path_buf(), environment.clone());
+        Ok(environment)
+    }
+
+    /// The table holding a workspace's declared globals, which falls back to its environment.
+    fn shared_globals(
+        &mut self,
+        lua: &Lua,
+        context: &ScriptContext
+    ) -> mlua::Result<Table> {
+        let root = context.workspace_root();
+        if let Some(shared) = self.shared_globals.get(root) {
+            return Ok(shared.clone());
+        }
+        let shared = lua.create_table()?;
+        let metatable = lua.create_table()?;
+        metatable.raw_set("__index", self.workspace_environment(lua, context)?)?;
+        shared.set_metatable(Some(metatable));
+        self.shared_globals.insert(root.to_path_buf(), shared.clone());
+        Ok(shared)
+    }
+}
+
+/// Modules required with this prefix are Ludo's built-in libraries, rather than aliases.
+const BUILTIN_PREFIX: &str = "@ludo/";
+
+/// Loads one of Ludo's built-in libraries, if the requiring workspace has permission to use it.
+fn require_builtin(
+    lua: &Lua,
+    loader: &Rc<RefCell<ModuleLoader>>,
+    requirer: &ScriptContext,
+    module_path: &str
+) -> mlua::Result<Value> {
+    let (name, root) = {
+        let loader = loader.borrow();
+        match module_path {
+            PROCESS_MODULE => (PROCESS_MODULE, find_permitted_workspace(&loader.process_permitted, requirer).cloned()),
+            FS_MODULE => (FS_MODULE, find_permitted_workspace(loader.fs_access.keys(), requirer).cloned()),
+            _ => return Err(LudoError::Resolution(format!("No built-in library named {module_path} (required from {})", requirer.script_location.display())).into())
+        }
+    };
+    let Some(root) = root else {
+        return Err(LudoError::PermissionDenied(format!("Permission denied for {name} in {}, required from {}",
+            requirer.workspace_root().join(".ludorc").display(),
+            requirer.script_location.display()
+        )).into());
+    };
+    // The filesystem library differs between workspaces, so libraries are cached per workspace.
+    if let Some(library) = loader.borrow().builtins.get(&(name, root.clone())) {
+        return Ok(library.clone());
+    }
+    let library = match name {
+        FS_MODULE => create_fs_library(lua, loader.borrow().fs_access[&root].clone())?,
+        _ => create_process_library(lua, &loader.borrow().script_args)?
+    };
+    let library = Value::Table(library);
+    loader.borrow_mut().builtins.insert((name, root), library.clone());
+    Ok(library)
+}
+
+/// Decides what a required module is named relative to. Modules reached through an alias are
+/// named after it, while relative requires keep the name root of the module requiring them.
+fn chunk_root_for(
+    requirer: &ScriptContext,
+    module_path: &str,
+    script_location: &Path
+) -> ChunkRoot {
+    let alias = module_path.strip_prefix('@')
+        .and_then(|path| path.split('/').next())
+        .and_then(|alias| Some((alias, requirer.luau_rc.aliases.get(alias)?)));
+    match alias {
+        Some((alias, directory)) if script_location.starts_with(directory) => ChunkRoot {
+            prefix: Some(format!("@{alias}")),
+            directory: directory.clone()
+        },
+        // An alias naming a script rather than a directory.
+        Some((_, directory)) => ChunkRoot {
+            prefix: None,
+            directory: directory.parent().unwrap_or(directory).to_path_buf()
+        },
+        None => requirer.chunk_root.clone()
+    }
+}
+
+fn create_require(
+    lua: &Lua,
+    loader: &Rc<RefCell<ModuleLoader>>,
+    context: &ScriptContext
+) -> mlua::Result<Function> {
+    let loader = loader.clone();
+    let requirer_context = context.clone();
+    let luau_rc = context.luau_rc.clone();
+    let requirer = context.script_location.clone();
+    lua.create_function(move |lua, module_path: String| {
+        if module_path.starts_with(BUILTIN_PREFIX) {
+            return require_builtin(lua, &loader, &requirer_context, &module_path);
+        }
+        let resolved = resolve_module_path(&luau_rc, &requirer, Path::new(&module_path))
+            .with_context(|| format!("Failed to resolve module {module_path} required from {}", requirer.display()))
+            .map_err(|err| LudoError::wrap(ErrorKind::Resolution, err))?;
+        let script_location = locate_module_script(&resolved)
+            .with_context(|| format!("Failed to locate module {module_path} required from {}", requirer.display()))
+            .map_err(|err| LudoError::wrap(ErrorKind::Resolution, err))?
+            .ok_or_else(|| LudoError::Resolution(format!("No module found at {} (required as {module_path} from {})", resolved.display(), requirer.display())))?;
+        let script_location = script_location.canonicalize()
+            .with_context(|| format!("Failed to canonicalise module path {}", script_location.display()))
+            .map_err(|err| LudoError::wrap(ErrorKind::Resolution, err))?;
+        if let Some(module) = loader.borrow().modules.get(&script_location) {
+            return Ok(module.clone());
+        }
+        if let Some(index) = loader.borrow().loading.iter().position(|x| *x == script_location) {
+            let cycle: Vec<_> = loader.borrow().loading[index..].iter()
+                .chain([&script_location])
+                .map(|x| x.display().to_string())
+                .collect();
+            return Err(LudoError::Resolution(format!("Cyclic require detected: {}", cycle.join(" -> "))).into());
+        }
+        let user_rc = loader.borrow().user_rc.clone();
+        let mut context = ScriptContext::new_from_fs(user_rc, script_location.clone())
+            .with_context(|| format!("Failed to construct script context for {}", script_location.display()))
+            .map_err(lua_error)?;
+        context.chunk_root = chunk_root_for(&requirer_context, &module_path, &script_location);
+        if !loader.borrow().is_native_permitted(&context) {
+            return Err(LudoError::PermissionDenied(format!("Permission denied for native in {}, required from {}",
+                context.workspace_root().join(".ludorc").display(),
+                requirer.display()
+            )).into());
+        }
+        let module = load_module(lua, &loader, &context, MultiValue::new())?;
+        loader.borrow_mut().modules.insert(script_location, module.clone());
+        Ok(module)
+    })
+}
+
+/// Creates the global environment for a single module: its own `require`, falling back to the
+/// environment of the module's workspace.
+pub fn create_module_environment(
+    lua: &Lua,
+    loader: &Rc<RefCell<ModuleLoader>>,
+    context: &ScriptContext
+) -> mlua::Result<Table> {
+    let workspace_environment = loader.borrow_mut().workspace_environment(lua, context)?;
+    let environment = lua.create_table()?;
+    environment.raw_set("require", create_require(lua, loader, context)?)?;
+    let metatable = lua.create_table()?;
+    // Globals declared in .luaurc are shared with the rest of the workspace, rather than kept to
+    // this module, except for the ones Ludo provides itself.
+    let mut declared = HashSet::new();
+    for name in &context.luau_rc.globals {
+        if workspace_environment.raw_get::<Value>(name.as_str())?.is_nil() {
+            declared.insert(name.clone());
+        }
+    }
+    if declared.is_empty() {
+        metatable.raw_set("__index", workspace_environment)?;
+    } else {
+        let shared = loader.borrow_mut().shared_globals(lua, context)?;
+        metatable.raw_set("__index", shared.clone())?;
+        metatable.raw_set("__newindex", lua.create_function(move |_, (environment, key, value): (Table, Value, Value)| {
+            match &key {
+                Value::String(name) if declared.contains(&*name.to_string_lossy()) => shared.raw_set(key, value),
+                _ => environment.raw_set(key, value)
+            }
+        })?)?;
+    }
+    environment.set_metatable(Some(metatable));
+    Ok(environment)
+}
+
+/// Compiles the script described by `context` into a function, running in its own module
+/// environment.
+pub fn load_chunk(
+    lua: &Lua,
+    loader: &Rc<RefCell<ModuleLoader>>,
+    context: &ScriptContext
+) -> mlua::Result<Function> {
+    let source = fs::read_to_string(&context.script_location)
+        .with_context(|| format!("Could not read source file at {}", context.script_location.display()))
+        .map_err(|err| LudoError::wrap(ErrorKind::Resolution, err))?;
+    let environment = create_module_environment(lua, loader, context)?;
+    loader.borrow_mut().chunk_paths.insert(context.display_name(), context.script_location.clone());
+    lua.load(source)
+        .set_name(context.lua_chunk_name())
+        .set_mode(ChunkMode::Text)
+        .set_environment(environment)
+        .into_function()
+}
+
+/// Runs the script described by `context` as a module, passing `args` as its varargs and
+/// returning the value it returns.
+pub fn load_module(
+    lua: &Lua,
+    loader: &Rc<RefCell<ModuleLoader>>,
+    context: &ScriptContext,
+    args: MultiValue
+) -> mlua::Result<Value> {
+    let chunk = load_chunk(lua, loader, context)?;
+    loader.borrow_mut().loading.push(context.script_location.clone());
+    let result = chunk.call(args);
+    loader.borrow_mut().loading.pop();
+    result
+}
+
+/// Removes globals which would let scripts get around module encapsulation: mlua's own `require`
+/// and `package`, and the functions which read, replace or compile environments.
+pub fn remove_unsafe_globals(
+    lua: &Lua
+) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for name in ["require", "package", "getfenv", "setfenv", "loadstring"] {
+        globals.raw_remove(name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+    use crate::native::collect_transitive_natives;
+    use crate::permissions::collect_fs_access;
+
+    fn run_main(
+        dir: &Path,
+        native: Option<&str>
+    ) -> mlua::Result<String> {
+        let lua = Lua::new();
+        remove_unsafe_globals(&lua)?;
+        lua.sandbox(true)?;
+        let loader = ModuleLoader::new(Rc::new(UserRc::default()));
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), dir.join("main/init.luau")).unwrap();
+        if let Some(native) = native {
+            let exports = lua.create_table()?;
+            exports.set("secret", native)?;
+            loader.borrow_mut().set_native_exports(context.workspace_root().to_path_buf(), Value::Table(exports));
+        }
+        load_module(&lua, &loader, &context, MultiValue::new())?.to_string()
+    }
+
+    #[test]
+    fn native_is_visible_inside_its_workspace() {
+        let dir = TempDir::with_files("native-visible", &[
+            ("main/.ludorc", r#"{ "version": 1 }"#),
+            ("main/init.luau", r#"return require("./helper")"#),
+            ("main/helper.luau", r#"return native.secret"#)
+        ]);
+        let result = run_main(&dir, Some("hello")).expect("Script should run");
+        assert_eq!(result, "hello");
+    }
+
+    #[test]
+    fn native_is_hidden_from_other_workspaces() {
+        let dir = TempDir::with_files("native-hidden", &[
+            ("main/.ludorc", r#"{ "version": 1 }"#),
+            ("main/init.luau", r#"
+                local other = require("../other")
+                assert(native.secret == "hello", "main workspace should see its native")
+                return other.saw_native
+            "#),
+            ("other/.ludorc", r#"{ "version": 1 }"#),
+            ("other/init.luau", r#"return { saw_native = native ~= nil }"#)
+        ]);
+        let result = run_main(&dir, Some("hello")).expect("Script should run");
+        assert_eq!(result, "false", "Unrelated modules should not see the native global");
+    }
+
+    #[test]
+    fn globals_do_not_leak_between_modules() {
+        let dir = TempDir::with_files("globals-leak", &[
+            ("main/init.luau", r#"
+                require("./writer")
+                return leaked
+            "#),
+            ("main/writer.luau", r#"
+                leaked = true
+                return nil
+            "#)
+        ]);
+        let result = run_main(&dir, None).expect("Script should run");
+        assert_eq!(result, "nil");
+    }
+
+    #[test]
+    fn declared_globals_are_shared_within_a_workspace() {
+        let dir = TempDir::with_files("declared-globals", &[
+            ("main/.luaurc", r#"{ "globals": ["counter", "native"] }"#),
+            ("main/init.luau", r#"
+                counter = 1
+                require("./writer")
+                local other = require("../other")
+                return table.concat({ counter, tostring(undeclared), tostring(other), native.secret }, ",")
+            "#),
+            ("main/writer.luau", r#"
+                counter += 1
+                undeclared = true
+                native = "forged"
+                return nil
+            "#),
+            ("other/.ludorc", r#"{ "version": 1 }"#),
+            ("other/.luaurc", r#"{ "globals": ["counter"] }"#),
+            ("other/init.luau", r#"return counter"#)
+        ]);
+        let result = run_main(&dir, Some("hello")).expect("Script should run");
+        assert_eq!(result, "2,nil,nil,hello");
+    }
+
+    #[test]
+    fn requiring_an_unpermitted_native_fails() {
+        let dir = TempDir::with_files("native-unpermitted", &[
+            ("main/init.luau", r#"return require("../other")"#),
+            ("other/.ludorc", r#"{ "version": 1, "native": { "name": "other", "parent": ".", "entry_point": "luaopen_other" } }"#),
+            ("other/init.luau", r#"return "ran without permission""#)
+        ]);
+        let err = run_main(&dir, None).expect_err("Require should be denied");
+        assert!(err.to_string().contains("Permission denied for native"), "Unexpected error: {err}");
+    }
+
+    #[test]
+    fn requiring_a_native_permitted_through_a_chain_succeeds() {
+        let binary = libloading::library_filename("inner").to_string_lossy().into_owned();
+        let dir = TempDir::with_files("native-chain", &[
+            ("main/.luaurc", r#"{ "aliases": { "outer": "../outer" } }"#),
+            ("main/.ludorc", r#"{ "version": 1, "permissions": { "@outer": { "native": true } } }"#),
+            ("main/init.luau", r#"return require("@outer")"#),
+            ("outer/.luaurc", r#"{ "aliases": { "inner": "../inner" } }"#),
+            ("outer/.ludorc", r#"{ "version": 1, "permissions": { "@inner": { "native": true } } }"#),
+            ("outer/init.luau", r#"return require("@inner")"#),
+            ("inner/.ludorc", r#"{ "version": 1, "native": { "name": "inner", "parent": ".", "entry_point": "luaopen_inner" } }"#),
+            ("inner/init.luau", r#"return native.secret"#),
+            (&format!("inner/{binary}"), "not really a library")
+        ]);
+        let lua = Lua::new();
+        let loader = ModuleLoader::new(Rc::new(UserRc::default()));
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), dir.join("main/init.luau")).unwrap();
+        for transitive_native in collect_transitive_natives(&context).unwrap() {
+            loader.borrow_mut().add_native(transitive_native);
+        }
+        let exports = lua.create_table().unwrap();
+        exports.set("secret", "hello").unwrap();
+        loader.borrow_mut().set_native_exports(dir.join("inner"), Value::Table(exports));
+        let result = load_module(&lua, &loader, &context, MultiValue::new()).expect("Script should run");
+        assert_eq!(result.to_string().unwrap(), "hello");
+    }
+
+    #[test]
+    fn modules_cannot_overwrite_the_native_global() {
+        let dir = TempDir::with_files("native-overwrite", &[
+            ("main/init.luau", r#"
+                require("./writer")
+                return native.secret
+            "#),
+            ("main/writer.luau", r#"
+                native = { secret = "forged" }
+                return nil
+            "#)
+        ]);
+        let result = run_main(&dir, Some("hello")).expect("Script should run");
+        assert_eq!(result, "hello");
+    }
+
+    #[test]
+    fn modules_are_cached_by_canonical_path() {
+        let dir = TempDir::with_files("module-cache", &[
+            ("main/init.luau", r#"
+                local a = require("./counter")
+                local b = require("../main/counter")
+                return a == b and a.loads == 1
+            "#),
+            ("main/counter.luau", r#"
+                loads = (loads or 0) + 1
+                return { loads = loads }
+            "#)
+        ]);
+        let result = run_main(&dir, None).expect("Script should run");
+        assert_eq!(result, "true");
+    }
+
+    #[test]
+    fn cyclic_requires_are_reported() {
+        let dir = TempDir::with_files("cyclic-require", &[
+            ("main/init.luau", r#"return require("./a")"#),
+            ("main/a.luau", r#"return require("./b")"#),
+            ("main/b.luau", r#"return require("./a")"#)
+        ]);
+        let err = run_main(&dir, None).expect_err("Cyclic require should fail");
+        let message = err.to_string();
+        assert!(message.contains("Cyclic require detected"), "Unexpected error: {message}");
+        assert!(message.contains("a.luau -> ") && message.contains("b.luau -> "), "Cycle should be reported: {message}");
+    }
+
+    #[test]
+    fn init_lua_is_required_as_a_directory() {
+        let dir = TempDir::with_files("init-lua", &[
+            ("main/init.luau", r#"return require("./package")"#),
+            ("main/package/init.lua", r#"return "from init.lua""#)
+        ]);
+        let result = run_main(&dir, None).expect("Script should run");
+        assert_eq!(result, "from init.lua");
+    }
+
+    #[test]
+    fn unsafe_globals_are_removed() {
+        let dir = TempDir::with_files("unsafe-globals", &[
+            ("main/init.luau", r#"return package == nil and getfenv == nil and setfenv == nil and loadstring == nil"#)
+        ]);
+        let result = run_main(&dir, None).expect("Script should run");
+        assert_eq!(result, "true");
+    }
+
+    #[test]
+    fn main_chunk_receives_arguments() {
+        let dir = TempDir::with_files("main-arguments", &[
+            ("main/init.luau", r#"return table.concat({ ... }, ",")"#)
+        ]);
+        let lua = Lua::new();
+        let loader = ModuleLoader::new(Rc::new(UserRc::default()));
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), dir.join("main/init.luau")).unwrap();
+        let args = ["a", "b c"].into_iter().map(|arg| Value::String(lua.create_string(arg).unwrap())).collect();
+        let result = load_module(&lua, &loader, &context, args).expect("Script should run");
+        assert_eq!(result.to_string().unwrap(), "a,b c");
+    }
+
+    #[test]
+    fn process_library_requires_permission() {
+        let dir = TempDir::with_files("process-permission", &[
+            ("main/init.luau", r#"
+                local process = require("@ludo/process")
+                local ok, err = pcall(require, "../other")
+                assert(not ok and string.find(tostring(err), "Permission denied for @ludo/process"), tostring(err))
+                return typeof(process.spawn)
+            "#),
+            ("other/.ludorc", r#"{ "version": 1 }"#),
+            ("other/init.luau", r#"return require("@ludo/process")"#)
+        ]);
+        let lua = Lua::new();
+        let loader = ModuleLoader::new(Rc::new(UserRc::default()));
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), dir.join("main/init.luau")).unwrap();
+        loader.borrow_mut().permit_process(context.workspace_root().to_path_buf());
+        let result = load_module(&lua, &loader, &context, MultiValue::new()).expect("Script should run");
+        assert_eq!(result.to_string().unwrap(), "function");
+    }
+
+    #[test]
+    fn fs_library_is_limited_to_granted_directories() {
+        let dir = TempDir::with_files("fs-access", &[
+            ("main/.luaurc", r#"{ "aliases": { "other": "../other" } }"#),
+            ("main/.ludorc", r#"{ "version": 1, "permissions": { "@other": { "fs": ["../data"] } } }"#),
+            ("main/init.luau", r#"return require("@other")(...)"#),
+            ("other/.ludorc", r#"{ "version": 1 }"#),
+            ("other/init.luau", r#"
+                local fs = require("@ludo/fs")
+                return function(dir)
+                    fs.write(dir .. "/data/note.txt", "hello")
+                    local ok, err = pcall(fs.read, dir .. "/main/init.luau")
+                    assert(not ok and string.find(tostring(err), "Permission denied"), tostring(err))
+                    return fs.read(dir .. "/data/../data/note.txt")
+                end
+            "#),
+            ("data/.keep", "")
+        ]);
+        let lua = Lua::new();
+        let loader = ModuleLoader::new(Rc::new(UserRc::default()));
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), dir.join("main/init.luau")).unwrap();
+        loader.borrow_mut().set_fs_access(collect_fs_access(&context).unwrap());
+        let args = MultiValue::from_vec(vec![Value::String(lua.create_string(dir.to_str().unwrap()).unwrap())]);
+        let result = load_module(&lua, &loader, &context, args).expect("Script should run");
+        assert_eq!(result.to_string().unwrap(), "hello");
+    }
+
+    #[test]
+    fn chunks_are_named_relative_to_workspace_or_alias() {
+        let dir = TempDir::with_files("chunk-names", &[
+            ("main/.luaurc", r#"{ "aliases": { "tool": "../vendor/tool" } }"#),
+            ("main/init.luau", r#"return require("./lib/util")"#),
+            ("main/lib/util.luau", r#"return require("@tool")"#),
+            ("vendor/tool/init.luau", r#"return require("./helpers/names")"#),
+            ("vendor/tool/helpers/names.luau", r#"return debug.info(1, "s")"#)
+        ]);
+        let result = run_main(&dir, None).expect("Script should run");
+        assert_eq!(result, "@tool/helpers/names.luau");
+        let lua = Lua::new();
+        let loader = ModuleLoader::new(Rc::new(UserRc::default()));
+        let context = ScriptContext::new_from_fs(Rc::new(UserRc::default()), dir.join("main/init.luau")).unwrap();
+        load_module(&lua, &loader, &context, MultiValue::new()).unwrap();
+        let mut names: Vec<_> = loader.borrow().chunk_paths().keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, ["@tool/helpers/names.luau", "@tool/init.luau", "init.luau", "lib/util.luau"]);
+    }
+}
@@ -1,53 +1,96 @@
-use std::fs;
-use std::io::{stdin, stdout, Read, Write};
+use std::cell::RefCell;
 use std::path::{Path, PathBuf};
-use std::process::exit;
 use std::rc::Rc;
-use ansi_term::Color::{Blue, Red, Yellow};
-use ansi_term::Style;
+use ansi_term::Color::{Blue, Red};
 use anyhow::{bail, Context, Result};
-use mlua::{ChunkMode, Compiler, ErrorContext, Lua};
-use mlua::prelude::LuaFunction;
-use crate::native::{collect_transitive_natives, load_native_library, TransitiveNative};
+use mlua::{Lua, MultiValue, Value};
+use thiserror::Error;
+use crate::bless::{bless_interactively, print_bless_info};
+use crate::limits::{Limiter, Limits};
+use crate::native::{collect_transitive_natives, TransitiveNative};
+use crate::permissions::{collect_fs_access, collect_permitted_workspaces};
+use crate::process::ExitRequested;
+use crate::diagnostics::format_lua_error;
+use crate::error::{lua_error_kind, ErrorKind, LudoError};
+use crate::fs_util::relative_path;
 use crate::luaurc::{load_composite_luau_rc, CanonicalLuauRc};
-use crate::ludorc::{load_workspace_rc, load_user_rc, UserRc, WorkspaceRc};
+use crate::ludorc::{load_workspace_rc, UserRc, WorkspaceRc};
+use crate::require::{load_chunk, remove_unsafe_globals, ModuleLoader};
+use crate::task::{create_task_library, run_to_completion, Scheduler};
+use crate::trust::{AllowList, NotBlessedError, TrustPolicy};
+
+/// The directory a script's chunk name is relative to, shown behind an optional prefix such as
+/// the .luaurc alias that reached it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkRoot {
+    pub prefix: Option<String>,
+    pub directory: PathBuf
+}
 
 #[derive(Debug, Clone)]
 pub struct ScriptContext {
     pub user_rc: Rc<UserRc>,
     pub workspace_rc: WorkspaceRc,
     pub luau_rc: CanonicalLuauRc,
-    pub script_location: PathBuf
+    pub script_location: PathBuf,
+    pub chunk_root: ChunkRoot
 }
 
 impl ScriptContext {
+    /// Describes the script at `script_location`, named relative to its workspace root.
     pub fn new_from_fs(
         user_rc: Rc<UserRc>,
         script_location: PathBuf
     ) -> Result<Self> {
         let workspace = script_location.parent().context("Ludo scripts must exist inside of a workspace")?;
-        let workspace_rc = load_workspace_rc(&workspace).context("Failed to construct workspace .ludorc")?;
-        let luau_rc = load_composite_luau_rc(&workspace).context("Failed to construct .luaurc")?;
-        Ok(Self { user_rc, workspace_rc, luau_rc, script_location })
+        let workspace_rc = load_workspace_rc(workspace).context("Failed to construct workspace .ludorc")?;
+        let luau_rc = load_composite_luau_rc(workspace).context("Failed to construct .luaurc")?;
+        let directory = workspace_rc.root.clone().unwrap_or_else(|| workspace.to_path_buf());
+        let chunk_root = ChunkRoot { prefix: None, directory };
+        Ok(Self { user_rc, workspace_rc, luau_rc, script_location, chunk_root })
+    }
+
+    /// The directory whose .ludorc governs this script, or the script's own directory if there
+    /// isn't one.
+    pub fn workspace_root(&self) -> &Path {
+        self.workspace_rc.root.as_deref()
+            .or(self.script_location.parent())
+            .unwrap_or(&self.script_location)
+    }
+
+    /// The name this script goes by in error messages and tracebacks, such as `src/main.luau` or
+    /// `@tool/init.luau`.
+    pub fn display_name(&self) -> String {
+        let relative = relative_path(&self.chunk_root.directory, &self.script_location);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        match &self.chunk_root.prefix {
+            Some(prefix) => format!("{prefix}/{relative}"),
+            None => relative
+        }
     }
 
     pub fn lua_chunk_name(
         &self
     ) -> String {
-        let friendly_name = self.script_location.file_stem()
-            .or(self.script_location.file_name())
-            .unwrap_or(self.script_location.as_os_str());
-        format!("@TODO-PATH/{}", friendly_name.to_string_lossy())
+        format!("@{}", self.display_name())
     }
 }
 
-pub fn terminate_if_not_blessed(
-    context: &ScriptContext,
-) -> Result<()> {
-    let transitive_natives = collect_transitive_natives(&context)?;
-    let not_blessed: Vec<_> = transitive_natives.iter().filter(|x| !x.is_blessed()).collect();
-    if not_blessed.is_empty() { return Ok(()) }
+/// Settings for a single run of a script, usually decided on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub trust_policy: TrustPolicy,
+    pub allow_list: AllowList,
+    /// Limits given on the command line, which take precedence over the main script's .ludorc.
+    pub limits: Limits,
+    /// Arguments passed through to the main script.
+    pub script_args: Vec<String>
+}
 
+fn print_not_blessed_warning(
+    context: &ScriptContext,
+    not_blessed: &[&TransitiveNative]
+) {
     println!("\n");
     println!("{}", Red.bold().blink().paint("==================== Hold up! ===================="));
     println!();
@@ -58,47 +101,132 @@ pub fn terminate_if_not_blessed(
     println!("These are the native libraries Ludo found, alongside their hashes:");
     for transitive_native in not_blessed {
         println!();
-        println!("-> {}", Yellow.bold().paint(&transitive_native.bless.title));
-        println!("   hash: {}", &transitive_native.bless.hash);
-
-        println!("   {}", Style::new().dimmed().paint(&transitive_native.bless.path.display().to_string()));
+        print_bless_info(&transitive_native.bless);
     }
     println!();
     println!("Ensure these hashes match the public hash for the library you're using.");
     println!("(The public hash may be found in the library's online documentation, for example.)");
     println!();
-    println!("Once verified, run {} to allow running these libraries next time.", Blue.paint("ludo bless"));
+    let bless_command = format!("ludo bless {}", context.script_location.display());
+    println!("Once verified, run {} to allow running these libraries next time.", Blue.paint(bless_command));
     println!();
     println!("{}", Red.bold().blink().paint("=================================================="));
     println!("\n");
+}
+
+/// Checks every native library the script could run against the trust policy, failing with a
+/// `NotBlessedError` if any of them may not run. Returns the verified native libraries.
+pub fn ensure_natives_trusted(
+    context: &ScriptContext,
+    options: &RunOptions
+) -> Result<Vec<TransitiveNative>> {
+    let transitive_natives = collect_transitive_natives(context)?;
+    let mut not_blessed: Vec<_> = transitive_natives.iter().filter(|x| !x.is_blessed()).collect();
+    if options.trust_policy == TrustPolicy::AllowListed {
+        not_blessed.retain(|x| !options.allow_list.contains(&x.bless));
+    }
+    if not_blessed.is_empty() { return Ok(transitive_natives) }
 
-    exit(1);
+    match options.trust_policy {
+        TrustPolicy::Deny => print_not_blessed_warning(context, &not_blessed),
+        TrustPolicy::Prompt => {
+            let blessed = bless_interactively(&not_blessed, false, None)?;
+            not_blessed.retain(|x| !blessed.contains(&x.bless.hash));
+        },
+        TrustPolicy::AllowListed => ()
+    }
+    if not_blessed.is_empty() { return Ok(transitive_natives) }
+    Err(LudoError::from(NotBlessedError {
+        policy: options.trust_policy,
+        natives: not_blessed.into_iter().map(|x| x.bless.clone()).collect()
+    }).into())
 }
 
-pub fn run_script(
+/// A sandboxed Luau state, with everything needed to load modules and schedule tasks in it.
+pub struct Runtime {
+    pub lua: Lua,
+    pub loader: Rc<RefCell<ModuleLoader>>,
+    pub scheduler: Rc<RefCell<Scheduler>>,
+    pub limiter: Rc<Limiter>
+}
+
+/// Creates a sandboxed Luau state able to run the script described by `context`, once its native
+/// libraries have been checked against the trust policy.
+pub fn create_runtime(
     context: &ScriptContext,
-) -> Result<()> {
-    terminate_if_not_blessed(&context)?;
-    let source = fs::read_to_string(&context.script_location)
-        .with_context(|| format!("Could not read source file at {}", context.script_location.display()))?;
+    options: &RunOptions
+) -> Result<Runtime> {
+    let transitive_natives = ensure_natives_trusted(context, options)?;
     let lua = Lua::new();
-    if let Some(native) = &context.workspace_rc.native {
-        unsafe { load_native_library(&lua, native) }.context("Failed to load native library")?;
+    let loader = ModuleLoader::new(context.user_rc.clone());
+    for transitive_native in transitive_natives {
+        loader.borrow_mut().add_native(transitive_native);
+    }
+    for permitted in collect_permitted_workspaces(context, |x| x.process)? {
+        loader.borrow_mut().permit_process(permitted.context.workspace_root().to_path_buf());
     }
+    loader.borrow_mut().set_fs_access(collect_fs_access(context)?);
+    loader.borrow_mut().set_script_args(options.script_args.clone());
+    let scheduler = Scheduler::new();
+    let Ok(_) = remove_unsafe_globals(&lua) else { bail!("Failed to remove unsafe globals") };
+    let Ok(task) = create_task_library(&lua, &scheduler) else { bail!("Failed to create task library") };
+    let Ok(_) = lua.globals().set("task", task) else { bail!("Failed to install task library") };
     let Ok(_) = lua.sandbox(true) else { bail!("Failed to initialise Luau sandbox") };
-    let func = lua.load(source)
-        .set_name(context.lua_chunk_name())
-        .set_mode(ChunkMode::Text);
-    match func.exec() {
-        Ok(_) => Ok(()),
-        Err(e) => bail!(e.to_string())
+    let limits = options.limits.or(context.workspace_rc.limits);
+    let Ok(limiter) = Limiter::install(&lua, limits) else { bail!("Failed to apply resource limits") };
+    Ok(Runtime { lua, loader, scheduler, limiter })
+}
+
+/// Returned when a script asks to exit with a non-zero status, rather than failing.
+#[derive(Error, Debug)]
+#[error("Script exited with status {0}")]
+pub struct ScriptExit(pub u8);
+
+/// Runs the main script, and then any tasks it scheduled until they've all finished. It receives
+/// the script arguments as varargs, and may return an integer to exit with that status.
+pub fn run_script(
+    context: &ScriptContext,
+    options: &RunOptions
+) -> Result<()> {
+    let Runtime { lua, loader, scheduler, limiter } = create_runtime(context, options)?;
+    let Ok(args) = options.script_args.iter()
+        .map(|arg| lua.create_string(arg).map(Value::String))
+        .collect::<mlua::Result<MultiValue>>()
+        else { bail!("Failed to pass arguments to script") };
+    limiter.start();
+    scheduler.borrow_mut().set_deadline(limiter.deadline());
+    let result = load_chunk(&lua, &loader, context)
+        .and_then(|main| run_to_completion(&lua, &scheduler, main, args));
+    if let Some(ExitRequested(status)) = ExitRequested::get(&lua) {
+        return if status == 0 { Ok(()) } else { Err(ScriptExit(status).into()) };
+    }
+    if let Some(limit) = limiter.exceeded(result.as_ref().err()) {
+        return Err(LudoError::from(limit).into());
+    }
+    let result = match result {
+        Ok(result) => result.into_iter().next().unwrap_or(Value::Nil),
+        Err(e) => {
+            let kind = lua_error_kind(&e).unwrap_or(ErrorKind::Runtime);
+            bail!(LudoError::of_kind(kind, format_lua_error(&e, loader.borrow().chunk_paths())))
+        }
+    };
+    let status = match result {
+        Value::Integer(status) => status as i64,
+        Value::Number(status) if status.fract() == 0.0 => status as i64,
+        _ => return Ok(())
+    };
+    let Ok(status) = u8::try_from(status) else { bail!(LudoError::Runtime(format!("Exit status must be between 0 and 255, but the script returned {status}"))) };
+    if status != 0 {
+        return Err(ScriptExit(status).into());
     }
+    Ok(())
 }
 
 pub fn run_from_fs(
     user_rc: Rc<UserRc>,
-    script_location: PathBuf
+    script_location: PathBuf,
+    options: &RunOptions
 ) -> Result<()> {
     let context = ScriptContext::new_from_fs(user_rc, script_location.clone()).context("Failed to construct script context")?;
-    run_script(&context)
+    run_script(&context, options)
 }
\ No newline at end of file
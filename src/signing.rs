@@ -0,0 +1,241 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use ansi_term::Color::{Green, Yellow};
+use ansi_term::Style;
+use anyhow::{bail, Context, Result};
+use base64ct::{Base64, Encoding};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use crate::ludorc::{TrustedKey, UserRc, UserRcStore};
+
+/// A publisher's signing key, as stored on disk by `ludo key generate`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub public_key: String,
+    pub secret_key: String
+}
+
+impl KeyFile {
+    pub fn load(
+        path: &Path
+    ) -> Result<Self> {
+        let contents = fs::read(path).with_context(|| format!("Failed to read key file at {}", path.display()))?;
+        serde_json::from_slice(&contents).with_context(|| format!("Failed to decode key file at {}", path.display()))
+    }
+
+    pub fn signing_key(&self) -> Result<SigningKey> {
+        let bytes = Base64::decode_vec(&self.secret_key).context("Secret key must be valid base64")?;
+        let bytes: [u8; 32] = bytes.try_into().ok().context("Secret key must be 32 bytes long")?;
+        Ok(SigningKey::from_bytes(&bytes))
+    }
+}
+
+pub fn decode_public_key(
+    public_key: &str
+) -> Result<VerifyingKey> {
+    let bytes = Base64::decode_vec(public_key).context("Public key must be valid base64")?;
+    let bytes: [u8; 32] = bytes.try_into().ok().context("Public key must be 32 bytes long")?;
+    VerifyingKey::from_bytes(&bytes).context("Public key is not a valid ed25519 key")
+}
+
+pub fn decode_signature(
+    signature: &str
+) -> Result<Signature> {
+    let bytes = Base64::decode_vec(signature).context("Signature must be valid base64")?;
+    Signature::from_slice(&bytes).context("Signature must be 64 bytes long")
+}
+
+/// Signatures are made over the SHA3-256 digest of a binary, which is the same digest its
+/// content hash encodes. This means signatures can be checked against a hash alone.
+fn signed_message(
+    hash: &str
+) -> Result<Vec<u8>> {
+    Base64::decode_vec(hash).context("Hash must be valid base64")
+}
+
+pub fn verify_hash(
+    public_key: &str,
+    hash: &str,
+    signature: &Signature
+) -> bool {
+    let Ok(public_key) = decode_public_key(public_key) else { return false };
+    let Ok(message) = signed_message(hash) else { return false };
+    public_key.verify_strict(&message, signature).is_ok()
+}
+
+/// Finds a trusted key which produced the signature for the binary with this hash.
+pub fn find_signer<'a>(
+    user_rc: &'a UserRc,
+    hash: &str,
+    signature: &Signature
+) -> Option<(&'a String, &'a TrustedKey)> {
+    user_rc.trusted_keys.iter().find(|(public_key, _)| verify_hash(public_key, hash, signature))
+}
+
+pub fn generate_key(
+    key_file: &Path,
+    name: Option<String>
+) -> Result<()> {
+    let mut seed = [0u8; 32];
+    getrandom::fill(&mut seed).map_err(|err| anyhow::anyhow!("Failed to generate random key: {err}"))?;
+    let signing_key = SigningKey::from_bytes(&seed);
+    let public_key = Base64::encode_string(signing_key.verifying_key().as_bytes());
+    let key = KeyFile { name, public_key: public_key.clone(), secret_key: Base64::encode_string(&seed) };
+    let mut contents = serde_json::to_vec_pretty(&key).context("Failed to encode key file")?;
+    contents.push(b'\n');
+    // Created afresh and private from the start, so the secret is never readable by others and
+    // an existing file is never overwritten.
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = match options.open(key_file) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists =>
+            bail!("A file already exists at {}; refusing to overwrite it", key_file.display()),
+        Err(err) => return Err(err).with_context(|| format!("Failed to create key file at {}", key_file.display()))
+    };
+    if let Err(err) = file.write_all(&contents).and_then(|_| file.sync_all()) {
+        let _ = fs::remove_file(key_file);
+        return Err(err).with_context(|| format!("Failed to write key file at {}", key_file.display()));
+    }
+    println!("{}", Green.paint(format!("Generated a signing key at {}.", key_file.display())));
+    println!("Keep this file secret. Share the public key with your users:");
+    println!("{public_key}");
+    Ok(())
+}
+
+pub fn sign_binary(
+    key_file: &Path,
+    binary: &Path
+) -> Result<()> {
+    let signing_key = KeyFile::load(key_file)?.signing_key()?;
+    let bytes = fs::read(binary).with_context(|| format!("Could not read native binary at {}", binary.display()))?;
+    let signature = signing_key.sign(&Sha3_256::digest(&bytes));
+    println!("{}", Base64::encode_string(&signature.to_bytes()));
+    Ok(())
+}
+
+pub fn verify_binary(
+    user_rc: &UserRc,
+    binary: &Path,
+    signature: &str,
+    public_key: Option<String>
+) -> Result<()> {
+    let signature = decode_signature(signature)?;
+    let bytes = fs::read(binary).with_context(|| format!("Could not read native binary at {}", binary.display()))?;
+    let hash = Base64::encode_string(&Sha3_256::digest(&bytes));
+    match public_key {
+        Some(public_key) => {
+            decode_public_key(&public_key)?;
+            if !verify_hash(&public_key, &hash, &signature) {
+                bail!("Signature was not made by {public_key} for {}", binary.display());
+            }
+            println!("{}", Green.paint(format!("Valid signature by {public_key}.")));
+        },
+        None => {
+            let Some((public_key, trusted)) = find_signer(user_rc, &hash, &signature)
+                else { bail!("Signature was not made by any trusted key for {}", binary.display()) };
+            let name = trusted.name.as_deref().unwrap_or("unnamed publisher");
+            println!("{}", Green.paint(format!("Valid signature by {name} ({public_key}).")));
+        }
+    }
+    Ok(())
+}
+
+pub fn trust_key(
+    public_key: String,
+    name: Option<String>
+) -> Result<()> {
+    decode_public_key(&public_key)?;
+    UserRcStore::at_home()?
+        .modify(|user_rc| { user_rc.trust_key(public_key.clone(), name); Ok(()) })
+        .context("Failed to save user .ludorc")?;
+    println!("{}", Green.paint(format!("Trusted {public_key}. Native libraries it signs will run without being blessed.")));
+    Ok(())
+}
+
+pub fn untrust_key(
+    public_key: String
+) -> Result<()> {
+    let removed = UserRcStore::at_home()?
+        .modify(|user_rc| Ok(user_rc.trusted_keys.remove(&public_key).is_some()))
+        .context("Failed to save user .ludorc")?;
+    if !removed {
+        bail!("{public_key} is not a trusted key");
+    }
+    println!("{}", Green.paint(format!("No longer trusting {public_key}.")));
+    Ok(())
+}
+
+pub fn list_trusted_keys(
+    user_rc: &UserRc
+) -> Result<()> {
+    if user_rc.trusted_keys.is_empty() {
+        println!("No publisher keys are trusted.");
+        return Ok(());
+    }
+    for (public_key, trusted) in &user_rc.trusted_keys {
+        println!("-> {}", Yellow.bold().paint(trusted.name.as_deref().unwrap_or("(unnamed publisher)")));
+        println!("   public key: {public_key}");
+        if let Some(trusted_at) = &trusted.trusted_at {
+            println!("   {}", Style::new().dimmed().paint(format!("trusted at: {trusted_at}")));
+        }
+        println!();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_util::TempDir;
+
+    #[test]
+    fn signature_verifies_against_content_hash() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = Base64::encode_string(signing_key.verifying_key().as_bytes());
+        let hash = Base64::encode_string(&Sha3_256::digest(b"binary"));
+        let signature = signing_key.sign(&Sha3_256::digest(b"binary"));
+        assert!(verify_hash(&public_key, &hash, &signature));
+
+        let other_hash = Base64::encode_string(&Sha3_256::digest(b"tampered"));
+        assert!(!verify_hash(&public_key, &other_hash, &signature), "Signature should not cover other binaries");
+    }
+
+    #[test]
+    fn only_trusted_keys_are_signers() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = Base64::encode_string(signing_key.verifying_key().as_bytes());
+        let hash = Base64::encode_string(&Sha3_256::digest(b"binary"));
+        let signature = signing_key.sign(&Sha3_256::digest(b"binary"));
+        let mut user_rc = UserRc::default();
+        assert!(find_signer(&user_rc, &hash, &signature).is_none());
+        user_rc.trust_key(public_key.clone(), None);
+        assert_eq!(find_signer(&user_rc, &hash, &signature).map(|(key, _)| key), Some(&public_key));
+    }
+
+    #[test]
+    fn generated_keys_are_private_and_never_overwritten() {
+        let dir = TempDir::new("generate-key");
+        let key_file = dir.join("publisher.key");
+        generate_key(&key_file, None).expect("Key should be generated");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&key_file).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let contents = fs::read(&key_file).unwrap();
+        assert!(generate_key(&key_file, None).is_err(), "An existing key file should not be overwritten");
+        assert_eq!(fs::read(&key_file).unwrap(), contents);
+    }
+}
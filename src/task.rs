@@ -0,0 +1,445 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use mlua::{Function, Lua, LightUserData, MultiValue, Table, Thread, ThreadStatus, Value};
+use crate::process::ExitRequested;
+
+/// Completions reported by native libraries, possibly from other threads.
+#[derive(Default)]
+struct ExternalQueue {
+    completed: Mutex<Vec<u64>>,
+    condvar: Condvar
+}
+
+/// Lets native libraries wake Luau threads waiting on `task.await` once their I/O completes. It's
+/// exposed to Luau as the `task.hook` light userdata, which only lives as long as the script, so
+/// natives should copy its fields rather than keep the pointer.
+///
+/// `complete` may be called from any thread. A native which completes work after the call which
+/// started it returns must first `retain` the context, and `release` it once it's done, as the
+/// script may finish and free the scheduler in the meantime.
+#[repr(C)]
+pub struct CompletionHook {
+    pub context: *const c_void,
+    pub complete: unsafe extern "C" fn(context: *const c_void, id: u64),
+    pub retain: unsafe extern "C" fn(context: *const c_void),
+    pub release: unsafe extern "C" fn(context: *const c_void)
+}
+
+unsafe extern "C" fn complete_external(
+    context: *const c_void,
+    id: u64
+) {
+    let queue = unsafe { &*(context as *const ExternalQueue) };
+    queue.completed.lock().unwrap_or_else(|err| err.into_inner()).push(id);
+    queue.condvar.notify_all();
+}
+
+unsafe extern "C" fn retain_external(
+    context: *const c_void
+) {
+    unsafe { Arc::increment_strong_count(context as *const ExternalQueue) };
+}
+
+unsafe extern "C" fn release_external(
+    context: *const c_void
+) {
+    unsafe { Arc::decrement_strong_count(context as *const ExternalQueue) };
+}
+
+/// Drives Luau threads which are waiting to run, on timers, or on native libraries.
+pub struct Scheduler {
+    ready: VecDeque<(Thread, MultiValue)>,
+    timers: BTreeMap<(Instant, u64), (Thread, MultiValue)>,
+    next_timer: u64,
+    awaiting: HashMap<u64, Thread>,
+    /// External completions which arrived before anything awaited them.
+    completed: HashSet<u64>,
+    next_external: u64,
+    external: Arc<ExternalQueue>,
+    hook: Box<CompletionHook>,
+    /// When to stop waiting for tasks, if the run has a timeout.
+    deadline: Option<Instant>,
+    /// The first error raised by a spawned task, which fails the run.
+    failure: Option<mlua::Error>
+}
+
+impl Scheduler {
+    pub fn new() -> Rc<RefCell<Self>> {
+        let external = Arc::new(ExternalQueue::default());
+        let hook = Box::new(CompletionHook {
+            context: Arc::as_ptr(&external) as *const c_void,
+            complete: complete_external,
+            retain: retain_external,
+            release: release_external
+        });
+        Rc::new(RefCell::new(Self {
+            ready: VecDeque::new(),
+            timers: BTreeMap::new(),
+            next_timer: 0,
+            awaiting: HashMap::new(),
+            completed: HashSet::new(),
+            next_external: 0,
+            external,
+            hook,
+            deadline: None,
+            failure: None
+        }))
+    }
+
+    pub fn set_deadline(
+        &mut self,
+        deadline: Option<Instant>
+    ) {
+        self.deadline = deadline;
+    }
+
+    fn is_past_deadline(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub fn defer(
+        &mut self,
+        thread: Thread,
+        args: MultiValue
+    ) {
+        self.ready.push_back((thread, args));
+    }
+
+    fn delay(
+        &mut self,
+        seconds: f64,
+        thread: Thread,
+        args: MultiValue
+    ) -> mlua::Result<()> {
+        let wake_at = Duration::try_from_secs_f64(seconds.max(0.0)).ok()
+            .and_then(|delay| Instant::now().checked_add(delay))
+            .ok_or_else(|| mlua::Error::runtime(format!("Cannot wait for {seconds} seconds, as it's too long")))?;
+        self.timers.insert((wake_at, self.next_timer), (thread, args));
+        self.next_timer += 1;
+        Ok(())
+    }
+
+    /// Records an error raised by a task which nothing else would see, to fail the run with.
+    fn fail(
+        &mut self,
+        err: mlua::Error
+    ) {
+        self.failure.get_or_insert(err);
+    }
+
+    fn cancel(
+        &mut self,
+        thread: &Thread
+    ) {
+        self.ready.retain(|(x, _)| x != thread);
+        self.timers.retain(|_, (x, _)| x != thread);
+        self.awaiting.retain(|_, x| x != thread);
+    }
+
+    fn await_external(
+        &mut self,
+        id: u64,
+        thread: Thread
+    ) {
+        if self.completed.remove(&id) {
+            self.ready.push_back((thread, MultiValue::new()));
+        } else {
+            self.awaiting.insert(id, thread);
+        }
+    }
+
+    /// Moves threads whose timers or native libraries have finished onto the ready queue.
+    fn wake(&mut self) {
+        let completed = std::mem::take(&mut *self.external.completed.lock().unwrap_or_else(|err| err.into_inner()));
+        for id in completed {
+            match self.awaiting.remove(&id) {
+                Some(thread) => self.ready.push_back((thread, MultiValue::new())),
+                None => { self.completed.insert(id); }
+            }
+        }
+        let now = Instant::now();
+        while let Some(entry) = self.timers.first_entry() {
+            if entry.key().0 > now { break }
+            let (thread, args) = entry.remove();
+            self.ready.push_back((thread, args));
+        }
+    }
+
+    /// Blocks until a timer is due, a native library completes something, or the deadline passes.
+    fn sleep(&self) {
+        let completed = self.external.completed.lock().unwrap_or_else(|err| err.into_inner());
+        if !completed.is_empty() { return }
+        let wake_at = self.timers.keys().next().map(|(wake_at, _)| *wake_at);
+        match wake_at.into_iter().chain(self.deadline).min() {
+            Some(wake_at) => {
+                let timeout = wake_at.saturating_duration_since(Instant::now());
+                drop(self.external.condvar.wait_timeout(completed, timeout));
+            },
+            None => drop(self.external.condvar.wait(completed))
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.ready.is_empty() && self.timers.is_empty() && self.awaiting.is_empty()
+    }
+}
+
+fn to_thread(
+    lua: &Lua,
+    task: Value
+) -> mlua::Result<Thread> {
+    match task {
+        Value::Function(function) => lua.create_thread(function),
+        Value::Thread(thread) => Ok(thread),
+        value => Err(mlua::Error::runtime(format!("Expected a function or thread to schedule, got {}", value.type_name())))
+    }
+}
+
+/// Creates the `task` library, which schedules Luau threads on `scheduler`.
+pub fn create_task_library(
+    lua: &Lua,
+    scheduler: &Rc<RefCell<Scheduler>>
+) -> mlua::Result<Table> {
+    let library = lua.create_table()?;
+
+    // Spawned tasks start straight away, but their errors fail the run rather than the caller, just
+    // as those of deferred tasks do.
+    let spawn_scheduler = scheduler.clone();
+    library.set("spawn", lua.create_function(move |lua, (task, args): (Value, MultiValue)| {
+        let thread = to_thread(lua, task)?;
+        if let Err(err) = thread.resume::<()>(args) {
+            spawn_scheduler.borrow_mut().fail(err);
+        }
+        Ok(thread)
+    })?)?;
+
+    let defer_scheduler = scheduler.clone();
+    library.set("defer", lua.create_function(move |lua, (task, args): (Value, MultiValue)| {
+        let thread = to_thread(lua, task)?;
+        defer_scheduler.borrow_mut().defer(thread.clone(), args);
+        Ok(thread)
+    })?)?;
+
+    let delay_scheduler = scheduler.clone();
+    library.set("delay", lua.create_function(move |lua, (seconds, task, args): (Option<f64>, Value, MultiValue)| {
+        let thread = to_thread(lua, task)?;
+        delay_scheduler.borrow_mut().delay(seconds.unwrap_or(0.0), thread.clone(), args)?;
+        Ok(thread)
+    })?)?;
+
+    let cancel_scheduler = scheduler.clone();
+    library.set("cancel", lua.create_function(move |_, thread: Thread| {
+        cancel_scheduler.borrow_mut().cancel(&thread);
+        Ok(())
+    })?)?;
+
+    let external_scheduler = scheduler.clone();
+    library.set("external", lua.create_function(move |_, ()| {
+        let mut scheduler = external_scheduler.borrow_mut();
+        scheduler.next_external += 1;
+        Ok(scheduler.next_external)
+    })?)?;
+
+    let hook = &*scheduler.borrow().hook as *const CompletionHook as *mut c_void;
+    library.set("hook", LightUserData(hook))?;
+
+    // Yielding has to happen in Luau, so these register the running thread and then yield it.
+    let wait_scheduler = scheduler.clone();
+    let schedule_wait = lua.create_function(move |_, (thread, seconds): (Thread, Option<f64>)| {
+        wait_scheduler.borrow_mut().delay(seconds.unwrap_or(0.0), thread, MultiValue::new())
+    })?;
+    let await_scheduler = scheduler.clone();
+    let schedule_await = lua.create_function(move |_, (thread, id): (Thread, u64)| {
+        await_scheduler.borrow_mut().await_external(id, thread);
+        Ok(())
+    })?;
+    let yielding: Table = lua.load(r#"
+        local scheduleWait, scheduleAwait, clock = ...
+        local function running()
+            if not coroutine.isyieldable() then
+                error("task functions which yield can only be called from inside a task", 3)
+            end
+            return coroutine.running()
+        end
+        return {
+            wait = function(seconds)
+                local started = clock()
+                scheduleWait(running(), seconds)
+                coroutine.yield()
+                return clock() - started
+            end,
+            await = function(id)
+                scheduleAwait(running(), id)
+                return coroutine.yield()
+            end
+        }
+    "#).set_name("=task").call((schedule_wait, schedule_await, lua.globals().get::<Table>("os")?.get::<Function>("clock")?))?;
+    library.set("wait", yielding.get::<Function>("wait")?)?;
+    library.set("await", yielding.get::<Function>("await")?)?;
+
+    library.set_readonly(true);
+    Ok(library)
+}
+
+/// Runs `function` as the main task, then keeps running scheduled tasks until none are left, or
+/// until one calls `process.exit` or fails. Returns whatever the main task returned, failing if it
+/// was left suspended with nothing scheduled to resume it.
+pub fn run_to_completion(
+    lua: &Lua,
+    scheduler: &Rc<RefCell<Scheduler>>,
+    function: Function,
+    args: MultiValue
+) -> mlua::Result<MultiValue> {
+    let main = lua.create_thread(function)?;
+    let mut result = MultiValue::new();
+    scheduler.borrow_mut().defer(main.clone(), args);
+    loop {
+        scheduler.borrow_mut().wake();
+        let next = scheduler.borrow_mut().ready.pop_front();
+        if let Some((thread, args)) = next {
+            if thread.status() != ThreadStatus::Resumable { continue }
+            let values = thread.resume::<MultiValue>(args);
+            if let Some(exit) = ExitRequested::get(lua) {
+                return Err(mlua::Error::external(exit));
+            }
+            if let Some(err) = scheduler.borrow_mut().failure.take() {
+                return Err(err);
+            }
+            let values = values?;
+            if thread == main && thread.status() == ThreadStatus::Finished {
+                result = values;
+            }
+            continue;
+        }
+        if scheduler.borrow().is_idle() { break }
+        if scheduler.borrow().is_past_deadline() {
+            return Err(mlua::Error::runtime("Deadline passed while waiting for tasks"));
+        }
+        scheduler.borrow().sleep();
+    }
+    if main.status() == ThreadStatus::Resumable {
+        return Err(mlua::Error::runtime("The main script yielded, but nothing was scheduled to resume it"));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(
+        source: &str
+    ) -> mlua::Result<String> {
+        let lua = Lua::new();
+        let scheduler = Scheduler::new();
+        lua.globals().set("task", create_task_library(&lua, &scheduler)?)?;
+        let function = lua.load(source).into_function()?;
+        let result = run_to_completion(&lua, &scheduler, function, MultiValue::new())?;
+        result.into_iter().next().unwrap_or(Value::Nil).to_string()
+    }
+
+    #[test]
+    fn tasks_run_in_scheduled_order() {
+        let result = run(r#"
+            local order = {}
+            task.delay(0.02, function() table.insert(order, "delay") end)
+            task.defer(function() table.insert(order, "defer") end)
+            task.spawn(function()
+                table.insert(order, "spawn")
+                task.wait(0.01)
+                table.insert(order, "wait")
+            end)
+            table.insert(order, "main")
+            task.wait(0.03)
+            return table.concat(order, ",")
+        "#).unwrap();
+        assert_eq!(result, "spawn,main,defer,wait,delay");
+    }
+
+    #[test]
+    fn cancelled_tasks_do_not_run() {
+        let result = run(r#"
+            local ran = false
+            local thread = task.delay(0.01, function() ran = true end)
+            task.cancel(thread)
+            task.wait(0.02)
+            return ran
+        "#).unwrap();
+        assert_eq!(result, "false");
+    }
+
+    #[test]
+    fn completion_hook_resumes_awaiting_tasks() {
+        let lua = Lua::new();
+        let scheduler = Scheduler::new();
+        let library = create_task_library(&lua, &scheduler).unwrap();
+        lua.globals().set("task", library.clone()).unwrap();
+        let LightUserData(hook) = library.get("hook").unwrap();
+        let hook = unsafe { &*(hook as *const CompletionHook) };
+        let (context, complete, release) = (hook.context as usize, hook.complete, hook.release);
+        lua.globals().set("startWork", lua.create_function(move |_, id: u64| {
+            unsafe { (hook.retain)(hook.context) };
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                unsafe { complete(context as *const c_void, id) };
+                unsafe { release(context as *const c_void) };
+            });
+            Ok(())
+        }).unwrap()).unwrap();
+        let function = lua.load(r#"
+            local id = task.external()
+            startWork(id)
+            task.await(id)
+            return "done"
+        "#).into_function().unwrap();
+        let result = run_to_completion(&lua, &scheduler, function, MultiValue::new()).unwrap();
+        assert_eq!(result.into_iter().next().unwrap().to_string().unwrap(), "done");
+    }
+
+    #[test]
+    fn completion_hook_outlives_the_scheduler_while_retained() {
+        let scheduler = Scheduler::new();
+        let (context, complete, retain, release) = {
+            let hook = &scheduler.borrow().hook;
+            (hook.context, hook.complete, hook.retain, hook.release)
+        };
+        unsafe { retain(context) };
+        drop(scheduler);
+        unsafe { complete(context, 1) };
+        unsafe { release(context) };
+    }
+
+    #[test]
+    fn overlong_delays_are_errors() {
+        let result = run(r#"
+            local ok, err = pcall(task.delay, math.huge, function() end)
+            assert(not ok and string.find(tostring(err), "too long"), tostring(err))
+            return tostring(select(2, pcall(task.wait, 1e300)))
+        "#).unwrap();
+        assert!(result.contains("too long"), "Unexpected result: {result}");
+    }
+
+    #[test]
+    fn main_yielding_with_nothing_to_resume_it_fails() {
+        let err = run(r#"
+            coroutine.yield()
+            return "resumed"
+        "#).unwrap_err();
+        assert!(err.to_string().contains("nothing was scheduled to resume it"), "Unexpected error: {err}");
+    }
+
+    #[test]
+    fn spawned_task_errors_fail_the_run() {
+        let err = run(r#"
+            local ok = pcall(task.spawn, function() error("inner") end)
+            assert(ok, "The caller should not see the spawned task's error")
+            task.wait(0)
+            return "finished"
+        "#).unwrap_err();
+        assert!(err.to_string().contains("inner"), "Unexpected error: {err}");
+    }
+}
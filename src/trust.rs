@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use thiserror::Error;
+use crate::native::BlessInfo;
+
+/// Environment variable holding pre-approved native hashes, separated by whitespace or commas.
+pub const TRUSTED_HASHES_VAR: &str = "LUDO_TRUSTED_HASHES";
+
+/// Decides what happens when a script tries to run native libraries that haven't been blessed.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrustPolicy {
+    /// Refuse to run, explaining how to bless the libraries.
+    #[default]
+    Deny,
+    /// Ask the user to bless the libraries before running.
+    Prompt,
+    /// Run libraries which have been pre-approved in an allow list, and refuse anything else
+    /// without prompting.
+    AllowListed
+}
+
+/// Native hashes pre-approved outside of the user .ludorc, for headless environments like CI.
+#[derive(Debug, Clone, Default)]
+pub struct AllowList {
+    hashes: HashSet<String>
+}
+
+impl AllowList {
+    fn extend_from_str(
+        &mut self,
+        text: &str
+    ) {
+        let hashes = text.lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|hash| !hash.is_empty())
+            .map(String::from);
+        self.hashes.extend(hashes);
+    }
+
+    /// Collects hashes from the `LUDO_TRUSTED_HASHES` environment variable, and from a file with
+    /// one hash per line if one is given. Text after a `#` is ignored.
+    pub fn from_env_and_file(
+        file: Option<&Path>
+    ) -> Result<Self> {
+        let mut allow_list = Self::default();
+        if let Ok(hashes) = env::var(TRUSTED_HASHES_VAR) {
+            allow_list.extend_from_str(&hashes);
+        }
+        if let Some(file) = file {
+            let hashes = fs::read_to_string(file)
+                .with_context(|| format!("Failed to read trusted hashes file at {}", file.display()))?;
+            allow_list.extend_from_str(&hashes);
+        }
+        Ok(allow_list)
+    }
+
+    pub fn contains(
+        &self,
+        info: &BlessInfo
+    ) -> bool {
+        self.hashes.contains(&info.hash)
+    }
+}
+
+/// Returned when a script can't run because some of its native libraries aren't trusted.
+#[derive(Error, Debug)]
+pub struct NotBlessedError {
+    pub policy: TrustPolicy,
+    pub natives: Vec<BlessInfo>
+}
+
+impl Display for NotBlessedError {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>
+    ) -> fmt::Result {
+        write!(f, "Refusing to run {} native {} that {} not been blessed",
+            self.natives.len(),
+            if self.natives.len() == 1 { "library" } else { "libraries" },
+            if self.natives.len() == 1 { "has" } else { "have" }
+        )?;
+        if self.policy == TrustPolicy::AllowListed {
+            write!(f, " or allow listed")?;
+        }
+        for native in &self.natives {
+            write!(f, "\n    {} ({}) at {}", native.title, native.hash, native.path.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allow_list_parses_lines_and_separators() {
+        let mut allow_list = AllowList::default();
+        allow_list.extend_from_str("# pre-approved for CI\nabc=\n  def=, ghi= # trailing comment\n\n");
+        let expected: HashSet<_> = ["abc=", "def=", "ghi="].into_iter().map(String::from).collect();
+        assert_eq!(allow_list.hashes, expected);
+    }
+}